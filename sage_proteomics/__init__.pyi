@@ -1,2 +1,6 @@
 from .sage import *
 from . import spectra as spectra
+from . import lfq as lfq
+from . import tmt as tmt
+from . import mass as mass
+from . import targeted as targeted
@@ -0,0 +1,161 @@
+from typing import Optional, List, Tuple, Union
+from .sage import Psm, ProteinGroup
+from .spectra import Mzml
+
+Tolerance = Union[float, Tuple[float, float]]
+
+class Xic:
+    rt: float
+    """Retention time of the MS1 scan"""
+    mass: float
+    """Sage internally uses masses instead of m/z - peaks are assumed to be z=1"""
+    intensity: float
+
+class FragmentXic:
+    precursor_mz: float
+    fragment_mz: List[float]
+    rt: List[float]
+    """Retention times of the MS2 scans whose isolation window covers the precursor"""
+    intensities: List[List[float]]
+    """
+    One trace per fragment, aligned on `rt`. Scans where a fragment was not
+    observed have zero intensity
+    """
+
+class ChromatographicPeak:
+    apex_rt: float
+    """Retention time of the (smoothed) peak apex"""
+    apex_intensity: float
+    """Raw intensity at the peak apex"""
+    start_rt: float
+    """Retention time of the left peak boundary"""
+    end_rt: float
+    """Retention time of the right peak boundary"""
+    area: float
+    """Area under the raw trace between `start_rt` and `end_rt`"""
+    fwhm: float
+    """Full width at half maximum of the smoothed trace"""
+    signal_to_noise: float
+    """Apex intensity divided by the median intensity outside of the peak"""
+    points: int
+    """Number of MS1 scans between the peak boundaries"""
+
+class PrecursorQuant:
+    peptide: str
+    """Peptide sequence, in ProForma notation"""
+    charge: int
+    """Precursor charge"""
+    proteins: List[str]
+    """Proteins from which this peptide may have originated"""
+    file: str
+    """File the precursor was quantified in"""
+    rt: float
+    """Retention time of the best scoring PSM for this precursor"""
+    intensity: Optional[float]
+    """Integrated peak area, or `None` if no peak was detected"""
+    peak: Optional[ChromatographicPeak]
+    mbr: bool
+    """Was this precursor transferred from another run (match-between-runs)?"""
+
+def quantify(
+    mzml: Mzml,
+    psms: List[Psm],
+    q_value_cutoff: Optional[float] = 0.01,
+    rt_tolerance: Optional[float] = 2.5,
+    ppm: Optional[Tolerance] = None,
+    da: Optional[Tolerance] = None,
+    smoothing: Optional[int] = 3,
+) -> List[PrecursorQuant]:
+    """
+    Label-free quantification of all confident peptide precursors in `mzml`.
+
+    PSMs are assigned q-values by target-decoy competition on hyperscore, and
+    only targets with q-value <= `q_value_cutoff` are kept. Precursors are
+    deduplicated by peptide and charge (keeping the best scoring PSM), and
+    the MS1 peak of each precursor is extracted and integrated in parallel
+    """
+
+class RtAlignment:
+    """
+    Piecewise linear retention time alignment of `file` onto `reference`
+    """
+
+    file: str
+    reference: str
+    knots: List[Tuple[float, float]]
+    """(rt, reference rt) knots of the piecewise linear model"""
+
+    def transform(self, rt: float) -> float:
+        """
+        Map a retention time from this run onto the reference run
+        """
+    def inverse(self, rt: float) -> float:
+        """
+        Map a retention time from the reference run onto this run
+        """
+
+def align(
+    runs: List[Mzml],
+    psms: List[List[Psm]],
+    q_value_cutoff: Optional[float] = 0.01,
+    segments: Optional[int] = 10,
+) -> List[RtAlignment]:
+    """
+    Align each run onto the run with the most confident precursors, using
+    precursors identified in both runs. `psms[i]` are the PSMs of `runs[i]`
+    """
+
+def match_between_runs(
+    runs: List[Mzml],
+    psms: List[List[Psm]],
+    q_value_cutoff: Optional[float] = 0.01,
+    rt_tolerance: Optional[float] = 2.5,
+    mbr_rt_tolerance: Optional[float] = 0.5,
+    ppm: Optional[Tolerance] = None,
+    da: Optional[Tolerance] = None,
+    smoothing: Optional[int] = 3,
+    segments: Optional[int] = 10,
+) -> List[PrecursorQuant]:
+    """
+    Quantify confident precursors across `runs`, transferring identifications
+    to runs where a precursor was not identified.
+
+    Transferred precursors are extracted within `mbr_rt_tolerance` of their
+    aligned retention time, and reported with `mbr = True`
+    """
+
+class ProteinMatrix:
+    proteins: List[str]
+    """Protein groups (row labels), indistinguishable proteins joined by ';'"""
+    samples: List[str]
+    """Samples (column labels), taken from `PrecursorQuant.file`"""
+    intensities: List[List[Optional[float]]]
+    """Protein intensity in each sample, `None` if missing"""
+    precursor_counts: List[List[int]]
+    """Number of quantified precursors contributing to each value"""
+    mbr_counts: List[List[int]]
+    """Number of contributing precursors transferred by match-between-runs"""
+    missing: List[int]
+    """Number of samples in which each protein is missing"""
+
+def protein_rollup(
+    quant: List[PrecursorQuant],
+    protein_groups: List[ProteinGroup],
+    method: Optional[str] = "maxlfq",
+    top_n: Optional[int] = 3,
+    q_value_cutoff: Optional[float] = 0.01,
+    min_ratios: Optional[int] = 1,
+) -> ProteinMatrix:
+    """
+    Roll up precursor quantification to a protein x sample matrix.
+
+    Precursors are assigned to protein groups via their unique and razor
+    peptides; decoy groups and groups above `q_value_cutoff` are skipped.
+
+    `method` is one of:
+    - "maxlfq": protein profile from pairwise median precursor log-ratios
+      (requiring at least `min_ratios` shared precursors), scaled to the
+      summed precursor intensity
+    - "topn": mean of the `top_n` most intense precursors in each sample
+    - "median_polish": Tukey's median polish of log2 intensities
+    """
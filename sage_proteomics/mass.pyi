@@ -0,0 +1,41 @@
+from typing import List, Optional, Tuple
+
+class Fragment:
+    ion: str
+    """Ion type, 'b' or 'y'"""
+    index: int
+    """Number of residues in the fragment"""
+    charge: int
+    mz: float
+
+def mz(mass: float, charge: int) -> float:
+    """Convert a neutral mass to m/z at `charge`. Raises a ValueError for charge 0"""
+
+def ppm(a: float, b: float) -> float:
+    """Mass error of `a` relative to `b`, in parts per million"""
+
+def peptide_mass(proforma: str) -> float:
+    """
+    Neutral monoisotopic mass of a peptide given in ProForma notation,
+    e.g. "[Acetyl]-PEM[Oxidation]TIDEK[+8.0142]". Modifications can be
+    names from the curated UNIMOD subset, accessions ("UNIMOD:35") or signed
+    mass shifts; unsigned numbers are rejected as ambiguous
+
+    Raises a ValueError if the sequence cannot be parsed
+    """
+
+def fragment_ladder(proforma: str, max_charge: Optional[int] = 1) -> List[Fragment]:
+    """
+    b- and y-ion ladders of a peptide given in ProForma notation, at charge
+    states 1 through `max_charge`. Raises a ValueError if `max_charge` is 0
+    """
+
+def isotope_envelope(
+    mass: float, charge: Optional[int] = None, peaks: Optional[int] = 5
+) -> List[Tuple[float, float]]:
+    """
+    Averagine isotope envelope for a molecule of neutral monoisotopic `mass`,
+    as `(position, relative abundance)` pairs, scaled so that the most
+    abundant isotope is 1. Positions are neutral masses, or m/z if `charge`
+    is given. Raises a ValueError for charge 0
+    """
@@ -1,6 +1,12 @@
-from typing import Optional, Dict, List
+from typing import Optional, Dict, List, Iterator, Tuple, Union, TYPE_CHECKING
 from . import spectra
 
+if TYPE_CHECKING:
+    import pyarrow
+
+Tolerance = Union[float, Tuple[float, float]]
+Mods = Union[Dict[str, float], str, List[str]]
+
 class AnnotatedPeak:
     mass: float
     """Sage internally uses masses instead of m/z - peaks are assumed to be z=1"""
@@ -10,6 +16,8 @@ class AnnotatedPeak:
     index: int
     """b1, b2, y3, y4, etc"""
     charge: int
+    ppm_error: float
+    """Signed mass error of the match, in ppm of the theoretical fragment"""
 
 class Psm:
     peptide: str
@@ -55,6 +63,66 @@ class Psm:
     """Number of candidate peptides scored for this spectrum"""
     poisson: float
     """log10 probability of matching this many peaks across all candidates"""
+    reporter_ions: Optional[List[float]]
+    """Isobaric reporter ion intensities, see `tmt.attach_reporter_ions`"""
+    rank: int
+    """Rank of this PSM among those reported for the spectrum (1 is best)"""
+    residual_score: float
+    """Fraction of the spectrum's intensity left after subtracting peaks matched by higher-ranked chimeric PSMs (1.0 outside of chimeric search)"""
+    features: Dict[str, float]
+    """Additional rescoring features, see `Predictions.attach` and `fit_rt_model`"""
+
+class ProteinGroup:
+    proteins: List[str]
+    """Indistinguishable proteins, identified by exactly the same peptides"""
+    subset_proteins: List[str]
+    """Proteins identified by a strict subset of this group's peptides"""
+    subsumable_proteins: List[str]
+    """Proteins whose peptides are all explained by this and other groups"""
+    peptides: List[str]
+    """All peptide sequences (unmodified) identified for this group"""
+    unique_peptides: List[str]
+    """Peptides that only map to this group"""
+    razor_peptides: List[str]
+    """Shared peptides assigned to this group by the razor principle"""
+    score: float
+    """Best hyperscore of any peptide in this group"""
+    decoy: bool
+    """Is this a group of decoy proteins?"""
+    q_value: float
+    """Protein-level q-value, calculated by picked target-decoy competition"""
+
+def infer_proteins(
+    psms: List[Psm],
+    q_value_cutoff: Optional[float] = 0.01,
+    decoy_tag: Optional[str] = "rev_",
+) -> List[ProteinGroup]:
+    """
+    Parsimonious protein inference (Occam's razor) from PSMs passing
+    `q_value_cutoff` (target-decoy competition on hyperscore).
+
+    Indistinguishable proteins are merged into groups, subset and subsumable
+    proteins are folded into the minimal set of groups explaining all
+    peptides, and shared peptides are assigned to the largest group as razor
+    peptides. Protein q-values are calculated by picked target-decoy
+    competition, pairing target groups with decoys carrying `decoy_tag`
+    """
+
+class Peptide:
+    index: int
+    """Index of this peptide in the database"""
+    sequence: str
+    """Peptide sequence, in ProForma notation"""
+    stripped_sequence: str
+    """Unmodified peptide sequence"""
+    monoisotopic: float
+    """Monoisotopic mass, including modifications"""
+    decoy: bool
+    """Is this a decoy peptide?"""
+    missed_cleavages: int
+    """Number of missed cleavages"""
+    proteins: List[str]
+    """Proteins containing this peptide sequence"""
 
 class Database:
     """
@@ -63,14 +131,115 @@ class Database:
 
     def __init__(
         self,
-        path: str,
+        fasta: str,
         decoy_tag: Optional[str] = "rev_",
         generate_decoys: Optional[bool] = True,
-        static_mods: Optional[Dict[str, float]] = None,
-        variable_mods: Optional[Dict[str, float]] = None,
+        static_mods: Optional[Mods] = None,
+        variable_mods: Optional[Mods] = None,
+        decoy_strategy: Optional[str] = "reverse",
+        decoy_seed: Optional[int] = 42,
     ) -> None:
         """
         Create a new Sage database
+
+        Modifications are given as a `{residue: mass}` dictionary (`^` for the
+        peptide N-terminus), or as one or more specifications of the form
+        `"<mod>@<site>"`:
+        - `<mod>` is a UNIMOD name ("Oxidation"), accession ("UNIMOD:35"),
+          or signed mass shift ("+15.9949"). Unsigned numbers are rejected
+          as ambiguous. Names are resolved against a curated subset of
+          UNIMOD (about 45 common modifications), not the full database
+        - `<site>` is one or more residues ("STY"), "N-term" or "Any". If
+          omitted, the common sites listed for the modification are used,
+          keeping only those that can be searched
+
+        The search engine (sage-core v0.7.1) only knows residues and the
+        peptide N-terminus: protein N-terminal and any C-terminal sites raise
+        a ValueError. Static modifications on the same site are summed, but
+        only one variable modification per site can be searched - a second
+        one raises a ValueError
+
+        `decoy_strategy` is one of:
+        - "reverse": sage's built-in decoy generation
+        - "pseudo_reverse": reverse each tryptic peptide, keeping the
+          C-terminal K/R fixed
+        - "shuffle": shuffle each tryptic peptide (seeded by `decoy_seed`),
+          keeping the C-terminal K/R fixed
+        - "debruijn": replace each tryptic peptide by a walk along a de Bruijn
+          sequence of amino acids (seeded by `decoy_seed`)
+
+        Randomized strategies seed each tryptic peptide from `decoy_seed` and
+        its sequence, so a peptide shared by several proteins always gets the
+        same decoy. Strategies other than "reverse" read `fasta` from the
+        local filesystem, and raise a ValueError if a generated decoy contains
+        an invalid amino acid
+        """
+    @staticmethod
+    def from_proteins(
+        proteins: Dict[str, str],
+        decoy_tag: Optional[str] = "rev_",
+        generate_decoys: Optional[bool] = True,
+        static_mods: Optional[Mods] = None,
+        variable_mods: Optional[Mods] = None,
+        decoy_strategy: Optional[str] = "reverse",
+        decoy_seed: Optional[int] = 42,
+    ) -> "Database":
+        """
+        Create a new Sage database from a dictionary of `{accession: sequence}`
+
+        Requires a writable temporary directory (`TMPDIR`): the search engine
+        only reads proteins from a file, so the sequences are staged there as
+        a FASTA file, deleted with the database. An IOError is raised if it is
+        not writable
+        """
+    @staticmethod
+    def from_peptides(
+        peptides: List[str],
+        decoy_tag: Optional[str] = "rev_",
+        generate_decoys: Optional[bool] = True,
+        static_mods: Optional[Mods] = None,
+        variable_mods: Optional[Mods] = None,
+        decoy_strategy: Optional[str] = "reverse",
+        decoy_seed: Optional[int] = 42,
+    ) -> "Database":
+        """
+        Create a new Sage database from a list of peptide sequences. Each
+        peptide is used as its own protein accession, and is indexed as given:
+        peptides are not cleaved, and the length and mass limits are widened
+        to admit every peptide (missed cleavages are reported as 0)
+
+        Like `from_proteins`, this requires a writable temporary directory
+        (`TMPDIR`), where the sequences are staged as a FASTA file
+        """
+    fragments: int
+    """Number of fragment ions in database"""
+    targets: int
+    """Number of target peptides in database"""
+    decoys: int
+    """Number of decoy peptides in database"""
+    def __len__(self) -> int: ...
+    def peptides(self) -> Iterator[Peptide]:
+        """
+        Iterate over all peptides in the database
+        """
+    def get_peptide(self, index: int) -> Peptide:
+        """
+        Return the peptide at `index`. Raises IndexError if out of range
+        """
+    def find_peptide(self, sequence: str) -> List[Peptide]:
+        """
+        Return all modified forms of `sequence` (targets and decoys) present
+        in the database, with their protein accessions. Modifications in
+        `sequence` are ignored
+        """
+    def proteins_for(self, sequence: str) -> List[str]:
+        """
+        Return the accessions of all proteins containing `sequence`
+        """
+    def decoy_collisions(self) -> List[str]:
+        """
+        Return decoy peptide sequences identical to a target peptide sequence
+        (treating I and L as equivalent)
         """
     def annotate_sequence(
         self,
@@ -92,18 +261,415 @@ class Database:
     ) -> List[AnnotatedPeak]:
         """
         Given a peptide-spectrum match, return a list of the matched peaks
+
+        Raises a ValueError for PSMs that did not come from a `Database`
+        search, e.g. spectral library matches
         """
     def search(
-        self, spectrum: spectra.Spectrum, report_psms: Optional[int] = 1
+        self,
+        spectrum: spectra.Spectrum,
+        report_psms: Optional[int] = 1,
+        precursor_ppm: Optional[Tolerance] = None,
+        precursor_da: Optional[Tolerance] = None,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+        open_search: Optional[bool] = False,
+        chimera: Optional[bool] = False,
     ) -> List[Psm]:
         """
         Search and score a single MS2 spectra, returning a list containing
         `report_psms` Psms
+
+        Tolerances can be given in ppm or Da (but not both), as a single value
+        or a `(lo, hi)` tuple. Defaults are +/- 20 ppm precursor and +/- 10 ppm
+        fragment tolerance. With `open_search`, the default precursor window is
+        -150 to +500 Da and isotope errors are not considered; the observed mass
+        shift is `expmass - calcmass`
+
+        With `chimera`, up to `report_psms` co-fragmented peptides are returned:
+        after each PSM, the fragment peaks it explains are subtracted and the
+        residual spectrum is re-scored. Chimeric PSMs never share a peptide
+        sequence, even with different modifications; their `hyperscore` is
+        against the residual spectrum, and `residual_score` is the fraction
+        of intensity that was left
         """
     def search_by_id(
-        self, file, title: str, report_psms: Optional[int] = 1
+        self,
+        file: spectra.Mzml,
+        title: str,
+        report_psms: Optional[int] = 1,
+        precursor_ppm: Optional[Tolerance] = None,
+        precursor_da: Optional[Tolerance] = None,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+        open_search: Optional[bool] = False,
+        chimera: Optional[bool] = False,
     ) -> List[Psm]:
         """
         Locate and score a single MS2 spectra from `file` identified by spectrum title.
         Return a list of Psms of length `report_psms`
         """
+    def calibrate(
+        self,
+        file: spectra.Mzml,
+        psms: List[Psm],
+        q_value_cutoff: Optional[float] = 0.01,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+    ) -> MassCalibration:
+        """
+        Fit the systematic precursor and fragment mass error (ppm) of `file`,
+        as a function of retention time and m/z, from its confident target
+        PSMs (q <= `q_value_cutoff`). Fragment errors are measured on the b-
+        and y-ions matched within the fragment tolerance
+        """
+    def search_recalibrated(
+        self,
+        file: spectra.Mzml,
+        report_psms: Optional[int] = 1,
+        q_value_cutoff: Optional[float] = 0.01,
+        precursor_ppm: Optional[Tolerance] = None,
+        precursor_da: Optional[Tolerance] = None,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+    ) -> Tuple[List[Psm], MassCalibration]:
+        """
+        Two-pass search of every MS2 spectrum in `file`
+
+        The first pass uses the given tolerances, and its confident PSMs are
+        used to fit a `MassCalibration`, which is applied to `file` in place.
+        The recalibrated spectra are then searched again with tolerances of
+        4 robust standard deviations of the remaining mass error (at least
+        1 ppm). Returns the second pass PSMs and the calibration
+        """
+    def search_dia(
+        self,
+        file: spectra.Mzml,
+        charges: Optional[List[int]] = [2, 3],
+        candidates: Optional[int] = 5,
+        cycles: Optional[int] = 3,
+        min_fragments: Optional[int] = 3,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+    ) -> List[DiaMatch]:
+        """
+        Spectrum-centric search of DIA data. Each MS2 scan is scored against
+        the peptides whose precursor m/z falls within its isolation window, at
+        each of `charges`, keeping the best `candidates` per scan and charge
+
+        Candidates are grouped across consecutive cycles of the same window.
+        The best scoring (apex) scan provides the PSM, and fragment XICs are
+        extracted from the `cycles` scans either side of it. Precursors with
+        fewer than `min_fragments` matched fragments are dropped, and
+        precursors found in overlapping windows are reported once
+        """
+
+class MassCalibration:
+    """
+    Systematic precursor and fragment mass error (ppm), modelled as a
+    quadratic function of retention time and m/z. With fewer than 50
+    observations, a constant (median) offset is used instead
+    """
+
+    precursor_points: int
+    """Number of PSMs used to fit the precursor mass error"""
+    fragment_points: int
+    """Number of matched fragment ions used to fit the fragment mass error"""
+    precursor_tolerance: Optional[Tuple[float, float]]
+    """Recommended precursor tolerance (ppm) after recalibration"""
+    fragment_tolerance: Optional[Tuple[float, float]]
+    """Recommended fragment tolerance (ppm) after recalibration"""
+
+    def precursor_offset(self, rt: float, mz: float) -> Optional[float]:
+        """Systematic precursor mass error (ppm) at a retention time and m/z"""
+    def fragment_offset(self, rt: float, mz: float) -> Optional[float]:
+        """Systematic fragment mass error (ppm) at a retention time and m/z"""
+    def apply(self, mzml: spectra.Mzml) -> None:
+        """
+        Recalibrate the spectra of `mzml` in place: precursor m/z and MS1
+        peaks with the precursor model, MSn peaks with the fragment model.
+        Apply each calibration only once
+        """
+
+class DiaMatch:
+    psm: Psm
+    """Best scoring PSM, from the scan at the chromatographic apex"""
+    isolation_window: Tuple[float, float]
+    """Isolation window (m/z) that the precursor was identified in"""
+    cycles: int
+    """Number of consecutive cycles around the apex in which the peptide was a candidate"""
+    fragments: List[str]
+    """Fragment ion labels, e.g. y7"""
+    fragment_mz: List[float]
+    """Fragment ion m/z (singly charged)"""
+    rt: List[float]
+    """Retention times of the scans in the fragment XICs"""
+    xics: List[List[float]]
+    """Fragment XICs, one trace per fragment, aligned on `rt`"""
+    coelution: float
+    """Mean correlation of each fragment XIC with the sum of the others"""
+
+class LibraryPeak:
+    mz: float
+    intensity: float
+    charge: int
+    """Fragment charge, 1 unless annotated otherwise"""
+    annotation: Optional[str]
+    """Fragment annotation, e.g. "y5^2", if present in the library"""
+
+class LibrarySpectrum:
+    peptide: str
+    """Peptide sequence, including modifications e.g.: PEM[+15.9949]TIDEK"""
+    charge: int
+    precursor_mz: float
+    rt: Optional[float]
+    """Retention time (or iRT), in the units used by the library"""
+    proteins: List[str]
+    decoy: bool
+    peaks: List[LibraryPeak]
+    """Fragment peaks, sorted by m/z"""
+
+class SpectralLibrary:
+    def __init__(self, path: str) -> None:
+        """
+        Load a spectral library in MSP (NIST) format, or a BiblioSpec-style
+        SQLite library (.blib). The format is detected from the file contents
+
+        MSP modifications (`Mods=` in the comment) are resolved against the
+        curated UNIMOD subset. Entries are marked as decoys if the comment
+        contains `Decoy=1` or a `Remark` mentioning DECOY
+        """
+    @staticmethod
+    def from_psms(
+        database: Database,
+        runs: List[spectra.Mzml],
+        psms: List[List[Psm]],
+        q_value_cutoff: Optional[float] = 0.01,
+        fragment_ppm: Optional[float] = 10.0,
+        consensus: Optional[bool] = False,
+        max_replicates: Optional[int] = 5,
+        min_peaks: Optional[int] = 6,
+    ) -> SpectralLibrary:
+        """
+        Build a library from confident PSMs, where `psms[i]` are the search
+        results of `runs[i]` against `database`. q-values are computed per run
+        by target-decoy competition on hyperscore
+
+        Each confident peptide and charge state contributes its best scoring
+        spectrum, annotated with matched b- and y-ions, with intensities scaled
+        to a base peak of 10000. With `consensus`, up to `max_replicates`
+        spectra are averaged, keeping fragments seen in at least half of them.
+        Entries with fewer than `min_peaks` annotated peaks are skipped
+
+        Retention times are aligned onto the run with the most confident
+        precursors, and the median across replicates is reported
+        """
+    def write(self, path: str, format: Optional[str] = None) -> None:
+        """
+        Write the library to `path` as MSP ("msp") or a BiblioSpec-style SQLite
+        library ("blib"). The format defaults to "blib" for paths ending in
+        .blib, .sqlite or .db, and to "msp" otherwise. Existing files are
+        overwritten. Decoy entries are not written to SQLite libraries
+        """
+    def __len__(self) -> int: ...
+    def get(self, index: int) -> LibrarySpectrum:
+        """Return the library spectrum at `index`, in order of precursor m/z"""
+    def search(
+        self,
+        spectrum: spectra.Spectrum,
+        report_psms: Optional[int] = 1,
+        precursor_ppm: Optional[Tolerance] = None,
+        precursor_da: Optional[Tolerance] = None,
+        fragment_ppm: Optional[Tolerance] = None,
+        fragment_da: Optional[Tolerance] = None,
+        similarity: Optional[str] = "dot",
+    ) -> List[Psm]:
+        """
+        Score library spectra within the precursor m/z tolerance (default
+        +/- 20 ppm), and of the same charge if known, against a MS2 spectrum.
+        Fragment tolerance defaults to +/- 10 ppm
+
+        `similarity` is "dot" (cosine of square-root intensities) or "entropy"
+        (entropy similarity). The score is stored in the `library_similarity`
+        feature, and its difference to the next best match in
+        `library_delta_similarity`; `Psm.hyperscore` is left at 0, so rank
+        and compute q-values on the feature rather than the hyperscore
+        """
+
+    sequence: str
+    protein: str
+    """Accession of the protein this peptide was digested from"""
+    start: int
+    """0-based start position in the protein sequence"""
+    end: int
+    """0-based, exclusive end position: `protein[start:end] == sequence`"""
+    previous_aa: str
+    """Residue preceding the peptide, or '-' at the protein N-terminus"""
+    next_aa: str
+    """Residue following the peptide, or '-' at the protein C-terminus"""
+    missed_cleavages: int
+    semi_enzymatic: bool
+    """True if only one terminus is an enzymatic cleavage site"""
+    monoisotopic: float
+    """Unmodified neutral monoisotopic mass"""
+
+def digest(
+    proteins: Union[str, Dict[str, str], List[str]],
+    cleave_at: Optional[str] = None,
+    restrict: Optional[str] = None,
+    missed_cleavages: Optional[int] = None,
+    min_len: Optional[int] = None,
+    max_len: Optional[int] = None,
+    semi: Optional[bool] = False,
+) -> List[DigestedPeptide]:
+    """
+    In-silico digestion without building a fragment index, using the same
+    cleavage rules as `Database`. `proteins` is a path to a FASTA file, a
+    dictionary of accessions to sequences, or a list of sequences (used as
+    their own accessions). Decoys are not generated
+
+    The enzyme cleaves after any residue in `cleave_at` (e.g. "KR"), unless
+    followed by the `restrict` residue (e.g. "P"), as in sage's `enzyme`
+    configuration. Unset options take the defaults of `Database` (trypsin).
+    With `semi`, peptides with one non-enzymatic terminus are also returned.
+    Sequences are uppercased, and must only contain letters; peptides
+    containing ambiguous residues (e.g. X) are skipped
+    """
+
+class Predictions:
+    """
+    Predicted fragment spectra and retention times (e.g. iRT), keyed by
+    peptide and charge
+    """
+
+    def __init__(self, path: str) -> None:
+        """
+        Load predictions from a CSV/TSV file, an MSP library or a .blib library
+
+        CSV files have one row per predicted fragment, e.g. Prosit's generic
+        spectral library export (ModifiedPeptide, PrecursorCharge, FragmentMz,
+        RelativeIntensity, iRT, ...) or MS2PIP output with a peptide column
+        added (log2 `prediction` intensities are converted back). Rows without
+        fragments provide retention time predictions only
+
+        Peptides are matched on residues and modification masses (to 0.01 Da),
+        so sage, ProForma, Prosit (`_PEM[Oxidation (M)]K_`) and MSP notations
+        may be mixed
+        """
+    def __len__(self) -> int: ...
+    def attach(
+        self,
+        mzml: spectra.Mzml,
+        psms: List[Psm],
+        q_value_cutoff: Optional[float] = 0.01,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+    ) -> Optional[Tuple[float, float]]:
+        """
+        Compute rescoring features for PSMs identified in `mzml`, stored in
+        `Psm.features`:
+
+        - `spectral_angle`: normalized spectral contrast angle between the
+          PSM's spectrum and the predicted spectrum (1 is a perfect match)
+        - `predicted_irt`: the predicted retention time, as given
+        - `calibrated_rt`: the prediction mapped onto the run's RT scale, by a
+          linear fit against confident target PSMs (q <= `q_value_cutoff`)
+        - `delta_rt`: |observed RT - calibrated RT|
+
+        Returns the (intercept, slope) of the RT calibration, if one could be
+        fitted
+        """
+
+class RtModel:
+    """
+    Linear retention time model on amino acid composition: the predicted RT
+    is `intercept + length * ln(len) + sum(coefficients[residue])`, summed
+    over the residues of the peptide. Modified residues (e.g. `M[+15.99]`)
+    have their own coefficients
+    """
+
+    intercept: float
+    length: float
+    """Coefficient of the natural logarithm of peptide length"""
+    coefficients: Dict[str, float]
+    """Retention coefficient of each (modified) residue"""
+    peptides: int
+    """Number of peptides the model was trained on"""
+    r_squared: float
+    """Coefficient of determination on the training peptides"""
+
+    def predict(self, peptide: str) -> float:
+        """
+        Predict the retention time of a (modified) peptide sequence. Residues
+        not seen during training contribute nothing
+        """
+
+def fit_rt_model(
+    psms: List[Psm],
+    q_value_cutoff: Optional[float] = 0.01,
+    alpha: Optional[float] = 1.0,
+) -> RtModel:
+    """
+    Train a retention time model on the confident target PSMs of a single
+    run (ridge regression with penalty `alpha`, each peptide contributing the
+    RT of its best scoring PSM), and set the `predicted_rt` and `rt_error`
+    (|observed - predicted|) features of every PSM. At least 10 confident
+    peptides are required
+
+    Training peptides are predicted by 5-fold cross-fitting, each by a model
+    that did not see it; all other PSMs by the returned model
+    """
+
+class MassShift:
+    delta_mass: float
+    """Median mass shift (expmass - calcmass) of the cluster, in Da"""
+    min_delta_mass: float
+    max_delta_mass: float
+    psms: int
+    """Number of PSMs in the cluster"""
+    annotations: List[str]
+    """UNIMOD modifications matching the mass shift"""
+
+def mass_shifts(
+    psms: List[Psm],
+    q_value_cutoff: Optional[float] = 0.01,
+    bin_width: Optional[float] = 0.01,
+    min_psms: Optional[int] = 5,
+    annotation_tolerance: Optional[float] = 0.02,
+) -> List[MassShift]:
+    """
+    Build a mass shift histogram from open search PSMs passing
+    `q_value_cutoff`. Sorted mass shifts are grouped into clusters spanning
+    at most `bin_width` Da; clusters with at least `min_psms` PSMs are returned,
+    most populated first, annotated with UNIMOD modifications within
+    `annotation_tolerance` Da
+    """
+
+def write_tsv(psms: List[Psm], path: str, filename: Optional[str] = None) -> None:
+    """
+    Write PSMs with the column layout of the sage command-line
+    `results.sage.tsv`. `filename` fills the `filename` column. Reporter ion
+    intensities are not written, as sage keeps them in a separate file
+
+    Spectrum and peptide q-values are computed by target-decoy competition on
+    the `sage_discriminant_score` feature, or the hyperscore if it is missing.
+    Only rank-1 PSMs compete for spectrum q-values; lower ranked PSMs get the
+    q-value of the score threshold at which they would be accepted.
+    `sage_discriminant_score`, `posterior_error`, `protein_q` and
+    `predicted_rt`/`delta_rt` (the `predicted_rt`/`rt_error` features set by
+    `fit_rt_model`) are taken from `Psm.features`, and left empty if missing
+    """
+
+def psms_to_arrow(psms: List[Psm]) -> "pyarrow.RecordBatch":
+    """
+    Convert PSMs to a `pyarrow.RecordBatch` via the Arrow C data interface,
+    with one column per `Psm` attribute (`proteins` and `reporter_ions` as
+    list columns), followed by one nullable float column per name found in
+    `Psm.features`. Requires the `pyarrow` build feature (on by default)
+    """
+
+def write_parquet(psms: List[Psm], path: str) -> None:
+    """
+    Write PSMs to a snappy-compressed Parquet file, with the same columns as
+    `psms_to_arrow`
+    """
@@ -1,4 +1,9 @@
-from typing import Optional, Dict, List
+from typing import Optional, Dict, List, Tuple, Union
+from .sage import Psm
+from .lfq import Xic, ChromatographicPeak, FragmentXic
+from .tmt import ReporterIons
+
+Tolerance = Union[float, Tuple[float, float]]
 
 class Peak:
     mass: float
@@ -10,6 +15,8 @@ class Precursor:
     intensity: Optional[float]
     charge: Optional[int]
     spectrum_ref: Optional[str]
+    isolation_window: Optional[Tuple[float, float]]
+    """Isolation window as absolute (lower, upper) m/z bounds, if known"""
 
 class Spectrum:
     level: int
@@ -37,3 +44,85 @@ class Mzml:
         """
     scans: int
     """Number of spectra in this file"""
+    def xic(
+        self,
+        psm: Psm,
+        charge: int,
+        rt_tolerance: Optional[float] = 2.5,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+    ) -> List[Xic]:
+        """
+        Extract MS1 peaks within `rt_tolerance` of the PSM that match the
+        monoisotopic precursor m/z at `charge`.
+
+        The m/z tolerance can be given in either `ppm` or `da` (but not both),
+        as a single value or a `(lo, hi)` tuple. Defaults to +/- 5 ppm
+        """
+    def fragment_xic(
+        self,
+        precursor_mz: float,
+        fragment_mz: List[float],
+        rt_min: Optional[float] = None,
+        rt_max: Optional[float] = None,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+    ) -> FragmentXic:
+        """
+        Extract fragment ion chromatograms from the MS2 scans whose isolation
+        window covers `precursor_mz`, optionally limited to `rt_min..rt_max`.
+        Each trace holds the most intense peak within tolerance (default
+        +/- 10 ppm) of a fragment in each scan. Fragment m/z are treated as
+        singly charged, matching the deconvoluted MS2 peaks
+        """
+    def isotope_xic(
+        self,
+        psm: Psm,
+        charge: int,
+        isotopes: Optional[int] = 3,
+        rt_tolerance: Optional[float] = 2.5,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+    ) -> List[List[Xic]]:
+        """
+        Extract MS1 peaks for the M, M+1, ... isotopes of the PSM precursor.
+        Element `i` of the returned list is the trace for isotope M+i
+        """
+    def integrate(
+        self,
+        psm: Psm,
+        charge: int,
+        rt_tolerance: Optional[float] = 2.5,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+        smoothing: Optional[int] = 3,
+    ) -> Optional[ChromatographicPeak]:
+        """
+        Detect the MS1 chromatographic peak of the PSM precursor at `charge`,
+        returning apex, boundaries, area, FWHM and signal-to-noise.
+
+        The trace is smoothed with a moving average of `smoothing` scans; the
+        peak containing the PSM retention time is preferred. Returns `None`
+        if no signal was found
+        """
+    def reporter_ions(
+        self,
+        label: str,
+        level: Optional[int] = None,
+        ppm: Optional[Tolerance] = None,
+        da: Optional[Tolerance] = None,
+        impurities: Optional[List[List[float]]] = None,
+    ) -> List[ReporterIons]:
+        """
+        Extract isobaric reporter ion intensities from every MSn scan.
+
+        `label` is one of "tmt6", "tmt10", "tmt11", "tmt16", "tmt18",
+        "itraq4" or "itraq8". If `level` is not given, MS3 scans are used when
+        present (SPS-MS3), otherwise MS2. Reporter ions are matched within
+        +/- 20 ppm unless `ppm` or `da` is supplied.
+
+        `impurities[i][j]` is the fraction of reagent `i` signal observed in
+        channel `j`; rows are normalized, so data sheet percentages can be
+        used directly. A row that does not sum to a positive value raises a
+        ValueError. Corrected intensities are clamped to be non-negative
+        """
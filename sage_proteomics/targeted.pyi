@@ -0,0 +1,73 @@
+from typing import Optional, List, Tuple, Union
+from .spectra import Mzml
+from .lfq import ChromatographicPeak, FragmentXic
+
+Tolerance = Union[float, Tuple[float, float]]
+
+class Transition:
+    peptide: str
+    protein: Optional[str]
+    precursor_mz: float
+    charge: int
+    fragment_mz: float
+    fragment_charge: int
+    fragment: Optional[str]
+    """Fragment ion label, e.g. y7"""
+    rt_start: Optional[float]
+    rt_end: Optional[float]
+    library_intensity: Optional[float]
+    """Relative library intensity, used for the library dot product"""
+    heavy: bool
+    """Transition of an isotope-labelled (heavy) internal standard"""
+
+class TargetedQuant:
+    peptide: str
+    protein: Optional[str]
+    charge: int
+    precursor_mz: float
+    heavy: bool
+    fragments: List[str]
+    """Fragment ion labels (or m/z, if unlabelled), in transition order"""
+    xic: FragmentXic
+    """Extracted fragment chromatograms, in transition order"""
+    peak: Optional[ChromatographicPeak]
+    """Peak detected in the summed fragment chromatogram"""
+    areas: List[float]
+    """Area of each fragment within the peak boundaries"""
+    total_area: float
+    library_dot_product: Optional[float]
+    """
+    Normalized dot product of square-root fragment areas and library
+    intensities, if library intensities are available
+    """
+    ratio_to_heavy: Optional[float]
+    """Ratio of the total area to that of the matching heavy standard"""
+
+def read_transitions(path: str) -> List[Transition]:
+    """
+    Read a PRM/SRM transition list from a CSV/TSV file, such as a Skyline
+    transition list export.
+
+    Recognized columns (case and punctuation insensitive) include Skyline's
+    "Peptide Modified Sequence", "Precursor Mz", "Precursor Charge",
+    "Product Mz", "Product Charge", "Fragment Ion", "Start Time"/"End Time"
+    or "Explicit Retention Time"/"Explicit Retention Time Window",
+    "Library Intensity" and "Isotope Label Type", as well as Q1/Q3.
+    """
+
+def quantify(
+    mzml: Mzml,
+    transitions: List[Transition],
+    ppm: Optional[Tolerance] = None,
+    da: Optional[Tolerance] = None,
+    smoothing: Optional[int] = 3,
+) -> List[TargetedQuant]:
+    """
+    Targeted (PRM) quantification.
+
+    Transitions are grouped by peptide, precursor charge and label. For each
+    precursor, fragment chromatograms are extracted from the MS2 scans whose
+    isolation window covers it, within the transition RT window, and
+    integrated over the peak detected in their sum. Light precursors are
+    paired with heavy standards of the same unmodified sequence and charge
+    """
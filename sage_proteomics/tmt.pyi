@@ -0,0 +1,23 @@
+from typing import List
+from .sage import Psm
+
+class ReporterIons:
+    spectrum_title: str
+    """Title of the spectrum containing the reporter ions"""
+    ms2_title: str
+    """
+    Title of the MS2 spectrum used for identification. For SPS-MS3 scans,
+    this is the MS2 scan referenced by `Precursor.spectrum_ref`
+    """
+    level: int
+    """MS level of the spectrum containing the reporter ions"""
+    channels: List[str]
+    """Channel names, e.g. 126, 127N, 127C"""
+    intensities: List[float]
+    """Reporter ion intensity for each channel, 0 if not observed"""
+
+def attach_reporter_ions(psms: List[Psm], reporter_ions: List[ReporterIons]) -> None:
+    """
+    Set `Psm.reporter_ions` for each PSM whose spectrum title matches the
+    `ms2_title` of a set of reporter ions
+    """
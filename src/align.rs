@@ -0,0 +1,120 @@
+use crate::stats::median;
+use pyo3::prelude::*;
+
+/// Piecewise linear retention time alignment, mapping retention times of one
+/// run onto the retention time scale of a reference run
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct RtAlignment {
+    /// File being aligned
+    #[pyo3(get)]
+    pub file: String,
+    /// Reference file
+    #[pyo3(get)]
+    pub reference: String,
+    /// Knots of the piecewise linear model, as (rt, reference rt) pairs
+    #[pyo3(get)]
+    pub knots: Vec<(f32, f32)>,
+}
+
+/// Evaluate the piecewise linear function defined by `knots` at `x`, linearly
+/// extrapolating beyond the first and last knots
+fn interpolate(knots: &[(f32, f32)], x: f32) -> f32 {
+    match knots.len() {
+        0 => x,
+        1 => x + (knots[0].1 - knots[0].0),
+        _ => {
+            let idx = knots
+                .windows(2)
+                .position(|w| x <= w[1].0)
+                .unwrap_or(knots.len() - 2);
+            let (x0, y0) = knots[idx];
+            let (x1, y1) = knots[idx + 1];
+            if (x1 - x0).abs() <= f32::EPSILON {
+                return y0;
+            }
+            y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+        }
+    }
+}
+
+impl RtAlignment {
+    /// Alignment that maps every retention time onto itself
+    pub fn identity(file: String) -> Self {
+        Self {
+            reference: file.clone(),
+            file,
+            knots: Vec::new(),
+        }
+    }
+
+    /// Fit a piecewise linear model from `(rt, reference rt)` pairs of
+    /// peptides identified in both runs. Pairs are sorted by `rt` and split
+    /// into up to `segments` equally populated bins; the median of each bin
+    /// becomes a knot of the model
+    pub fn fit(
+        file: String,
+        reference: String,
+        mut pairs: Vec<(f32, f32)>,
+        segments: usize,
+    ) -> Self {
+        pairs.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let bins = segments.max(1).min(pairs.len());
+        let mut knots: Vec<(f32, f32)> = Vec::with_capacity(bins);
+        for bin in 0..bins {
+            let lo = bin * pairs.len() / bins;
+            let hi = (bin + 1) * pairs.len() / bins;
+            let x = pairs[lo..hi].iter().map(|p| p.0).collect::<Vec<_>>();
+            let y = pairs[lo..hi].iter().map(|p| p.1).collect::<Vec<_>>();
+            if let (Some(x), Some(y)) = (median(&x), median(&y)) {
+                // Keep the model monotonic, so that it can be inverted
+                match knots.last() {
+                    Some(&(px, py)) if x <= px || y < py => {}
+                    _ => knots.push((x, y)),
+                }
+            }
+        }
+        Self {
+            file,
+            reference,
+            knots,
+        }
+    }
+
+    /// Map a retention time from this run onto the reference run
+    pub fn to_reference(&self, rt: f32) -> f32 {
+        interpolate(&self.knots, rt)
+    }
+
+    /// Map a retention time from the reference run onto this run
+    pub fn onto_run(&self, rt: f32) -> f32 {
+        let inverse = self.knots.iter().map(|&(x, y)| (y, x)).collect::<Vec<_>>();
+        interpolate(&inverse, rt)
+    }
+}
+
+#[pymethods]
+impl RtAlignment {
+    /// transform(rt)
+    /// --
+    /// Map a retention time from this run onto the reference run
+    fn transform(&self, rt: f32) -> f32 {
+        self.to_reference(rt)
+    }
+
+    /// inverse(rt)
+    /// --
+    /// Map a retention time from the reference run onto this run
+    fn inverse(&self, rt: f32) -> f32 {
+        self.onto_run(rt)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RtAlignment {} -> {} [{} knots]",
+            self.file,
+            self.reference,
+            self.knots.len()
+        )
+    }
+}
@@ -9,15 +9,18 @@ use sage_core::spectrum::ProcessedSpectrum;
 #[pyclass]
 pub struct AnnotatedPeak {
     #[pyo3(get)]
-    mass: f32,
+    pub mass: f32,
     #[pyo3(get)]
-    intensity: f32,
+    pub intensity: f32,
     #[pyo3(get)]
-    ion: char,
+    pub ion: char,
     #[pyo3(get)]
-    index: usize,
+    pub index: usize,
     #[pyo3(get)]
-    charge: u8,
+    pub charge: u8,
+    /// Signed mass error of the match, in ppm of the theoretical fragment
+    #[pyo3(get)]
+    pub ppm_error: f32,
 }
 
 #[pymethods]
@@ -31,7 +34,7 @@ impl AnnotatedPeak {
 pub fn annotate_peaks(
     query: &ProcessedSpectrum,
     peptide: Peptide,
-    tolerance_ppm: f32,
+    tolerance: Tolerance,
     charge: Option<u8>,
 ) -> Vec<AnnotatedPeak> {
     // Regenerate theoretical ions
@@ -55,7 +58,7 @@ pub fn annotate_peaks(
             (1..charge).flat_map(|charge| {
                 let peak = *peak;
                 let mass = peak.mass * charge as f32;
-                let (lo, hi) = Tolerance::Ppm(-tolerance_ppm, tolerance_ppm).bounds(mass);
+                let (lo, hi) = tolerance.bounds(mass);
                 let window = binary_search_slice(
                     &fragments,
                     |frag, mz| frag.1.monoisotopic_mass.total_cmp(mz),
@@ -81,6 +84,8 @@ pub fn annotate_peaks(
                             ion,
                             index: *index,
                             charge,
+                            ppm_error: (mass - frag.monoisotopic_mass) / frag.monoisotopic_mass
+                                * 1E6,
                         }
                     })
             })
@@ -0,0 +1,171 @@
+use crate::psm::Psm;
+use arrow::array::{
+    ArrayData, ArrayRef, BooleanArray, Float32Array, Float64Array, ListArray, StringArray,
+    UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow::buffer::Buffer;
+use arrow::datatypes::{DataType, Field, Float32Type};
+use arrow::error::ArrowError;
+use arrow::pyarrow::PyArrowConvert;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+fn proteins(psms: &[&Psm]) -> Result<ArrayRef, ArrowError> {
+    let mut offsets = Vec::with_capacity(psms.len() + 1);
+    offsets.push(0i32);
+    for psm in psms {
+        offsets.push(offsets[offsets.len() - 1] + psm.proteins.len() as i32);
+    }
+    let values = StringArray::from_iter_values(psms.iter().flat_map(|psm| psm.proteins.iter()));
+    let data = ArrayData::builder(DataType::List(Box::new(Field::new(
+        "item",
+        DataType::Utf8,
+        true,
+    ))))
+    .len(psms.len())
+    .add_buffer(Buffer::from_slice_ref(&offsets))
+    .add_child_data(values.data().clone())
+    .build()?;
+    Ok(Arc::new(ListArray::from(data)))
+}
+
+/// Convert PSMs to an Arrow record batch, with one column per `Psm` field,
+/// followed by one (nullable) column per name in `Psm.features`
+pub fn record_batch(psms: &[&Psm]) -> Result<RecordBatch, ArrowError> {
+    let strings = |f: fn(&Psm) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(psms.iter().map(|psm| f(psm))))
+    };
+    let f32s = |f: fn(&Psm) -> f32| -> ArrayRef {
+        Arc::new(Float32Array::from(
+            psms.iter().map(|psm| f(psm)).collect::<Vec<_>>(),
+        ))
+    };
+    let f64s = |f: fn(&Psm) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            psms.iter().map(|psm| f(psm)).collect::<Vec<_>>(),
+        ))
+    };
+    let u32s = |f: fn(&Psm) -> u32| -> ArrayRef {
+        Arc::new(UInt32Array::from(
+            psms.iter().map(|psm| f(psm)).collect::<Vec<_>>(),
+        ))
+    };
+    let u64s = |f: fn(&Psm) -> u64| -> ArrayRef {
+        Arc::new(UInt64Array::from(
+            psms.iter().map(|psm| f(psm)).collect::<Vec<_>>(),
+        ))
+    };
+    let u8s = |f: fn(&Psm) -> u8| -> ArrayRef {
+        Arc::new(UInt8Array::from(
+            psms.iter().map(|psm| f(psm)).collect::<Vec<_>>(),
+        ))
+    };
+
+    // `peptide_ix` is only meaningful to the `Database` that produced the PSM,
+    // and is not exported
+    let mut columns: Vec<(String, ArrayRef)> = vec![
+        ("peptide".into(), strings(|p| p.peptide.as_str())),
+        ("peptide_len".into(), u64s(|p| p.peptide_len as u64)),
+        ("proteins".into(), proteins(psms)?),
+        ("num_proteins".into(), u64s(|p| p.num_proteins as u64)),
+        (
+            "spectrum_title".into(),
+            strings(|p| p.spectrum_title.as_str()),
+        ),
+        (
+            "decoy".into(),
+            Arc::new(BooleanArray::from(
+                psms.iter().map(|psm| psm.decoy).collect::<Vec<_>>(),
+            )),
+        ),
+        ("expmass".into(), f32s(|p| p.expmass)),
+        ("calcmass".into(), f32s(|p| p.calcmass)),
+        ("charge".into(), u8s(|p| p.charge)),
+        ("rt".into(), f32s(|p| p.rt)),
+        ("delta_mass".into(), f32s(|p| p.delta_mass)),
+        ("isotope_error".into(), f32s(|p| p.isotope_error)),
+        ("average_ppm".into(), f32s(|p| p.average_ppm)),
+        ("hyperscore".into(), f64s(|p| p.hyperscore)),
+        ("delta_hyperscore".into(), f64s(|p| p.delta_hyperscore)),
+        ("matched_peaks".into(), u32s(|p| p.matched_peaks)),
+        ("longest_b".into(), u32s(|p| p.longest_b)),
+        ("longest_y".into(), u32s(|p| p.longest_y)),
+        ("longest_y_pct".into(), f32s(|p| p.longest_y_pct)),
+        ("missed_cleavages".into(), u8s(|p| p.missed_cleavages)),
+        (
+            "matched_intensity_pct".into(),
+            f32s(|p| p.matched_intensity_pct),
+        ),
+        ("scored_candidates".into(), u32s(|p| p.scored_candidates)),
+        ("poisson".into(), f64s(|p| p.poisson)),
+        (
+            "reporter_ions".into(),
+            Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(
+                psms.iter().map(|psm| {
+                    psm.reporter_ions
+                        .as_ref()
+                        .map(|ions| ions.iter().map(|&x| Some(x)).collect::<Vec<_>>())
+                }),
+            )),
+        ),
+        ("rank".into(), u32s(|p| p.rank)),
+        ("residual_score".into(), f64s(|p| p.residual_score)),
+    ];
+
+    let features = psms
+        .iter()
+        .flat_map(|psm| psm.features.keys())
+        .collect::<BTreeSet<_>>();
+    for name in features {
+        if columns.iter().any(|(column, _)| column == name) {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "feature `{}` has the same name as a Psm field",
+                name
+            )));
+        }
+        let values = psms
+            .iter()
+            .map(|psm| psm.features.get(name).copied())
+            .collect::<Vec<_>>();
+        columns.push((name.clone(), Arc::new(Float64Array::from(values))));
+    }
+    RecordBatch::try_from_iter(columns)
+}
+
+/// psms_to_arrow(psms)
+/// --
+/// Convert PSMs to a `pyarrow.RecordBatch`, via the Arrow C data interface
+#[pyfunction]
+pub fn psms_to_arrow(py: Python, psms: Vec<PyRef<Psm>>) -> PyResult<PyObject> {
+    let psms = psms.iter().map(|psm| &**psm).collect::<Vec<_>>();
+    record_batch(&psms)
+        .map_err(|e| PyErr::new::<PyValueError, _>(e.to_string()))?
+        .to_pyarrow(py)
+}
+
+/// write_parquet(psms, path)
+/// --
+/// Write PSMs to a (snappy compressed) Parquet file, with the same columns
+/// as `psms_to_arrow`
+#[pyfunction]
+pub fn write_parquet(psms: Vec<PyRef<Psm>>, path: &str) -> PyResult<()> {
+    let psms = psms.iter().map(|psm| &**psm).collect::<Vec<_>>();
+    let batch = record_batch(&psms).map_err(|e| PyErr::new::<PyValueError, _>(e.to_string()))?;
+
+    let io_error = |e: parquet::errors::ParquetError| PyErr::new::<PyIOError, _>(e.to_string());
+    let file = std::fs::File::create(path)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}: {}", path, e)))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).map_err(io_error)?;
+    writer.write(&batch).map_err(io_error)?;
+    writer.close().map_err(io_error)?;
+    Ok(())
+}
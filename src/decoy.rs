@@ -0,0 +1,159 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Strategy used to generate decoy protein sequences
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecoyStrategy {
+    /// Sage's built-in decoy generation
+    Reverse,
+    /// Reverse each tryptic peptide, keeping the C-terminal K/R fixed
+    PseudoReverse,
+    /// Shuffle each tryptic peptide with a fixed seed, keeping the C-terminal K/R fixed
+    Shuffle(u64),
+    /// Replace each tryptic peptide with a walk along a de Bruijn sequence of
+    /// amino acids, keeping the C-terminal K/R fixed
+    DeBruijn(u64),
+}
+
+impl DecoyStrategy {
+    pub fn parse(strategy: &str, seed: u64) -> PyResult<Self> {
+        match strategy.to_lowercase().replace(['-', '_'], "").as_str() {
+            "reverse" => Ok(DecoyStrategy::Reverse),
+            "pseudoreverse" => Ok(DecoyStrategy::PseudoReverse),
+            "shuffle" | "shuffled" => Ok(DecoyStrategy::Shuffle(seed)),
+            "debruijn" => Ok(DecoyStrategy::DeBruijn(seed)),
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unknown decoy strategy: {}. Expected one of reverse, pseudo_reverse, shuffle, debruijn",
+                strategy
+            ))),
+        }
+    }
+}
+
+/// SplitMix64 pseudo-random number generator, so that decoys are reproducible
+/// for a given seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// Seed a generator from `seed` and a tryptic segment (FNV-1a hash), so
+    /// that identical target peptides always map to the same decoy
+    fn for_segment(seed: u64, segment: &[u8]) -> Self {
+        let hash = segment.iter().fold(0xCBF29CE484222325 ^ seed, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001B3)
+        });
+        SplitMix64(hash)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Residues used to build de Bruijn decoys: cleavage sites (K, R) and proline
+/// are excluded, so that decoy peptides have the same cleavage pattern as targets
+const DE_BRUIJN_ALPHABET: &[u8] = b"ACDEFGHILMNQSTVWY";
+
+/// De Bruijn sequence of order 2 over [`DE_BRUIJN_ALPHABET`]: every pair of
+/// residues occurs exactly once in the (cyclic) sequence
+fn de_bruijn() -> Vec<u8> {
+    let k = DE_BRUIJN_ALPHABET.len();
+    // Standard recursive construction (Lyndon words), specialized to order 2
+    let mut a = [0usize; 3];
+    let mut sequence = Vec::with_capacity(k * k);
+    fn db(t: usize, p: usize, k: usize, a: &mut [usize; 3], sequence: &mut Vec<usize>) {
+        if t > 2 {
+            if 2 % p == 0 {
+                sequence.extend_from_slice(&a[1..=p]);
+            }
+        } else {
+            a[t] = a[t - p];
+            db(t + 1, p, k, a, sequence);
+            for j in a[t - p] + 1..k {
+                a[t] = j;
+                db(t + 1, t, k, a, sequence);
+            }
+        }
+    }
+    db(1, 1, k, &mut a, &mut sequence);
+    sequence
+        .into_iter()
+        .map(|i| DE_BRUIJN_ALPHABET[i])
+        .collect()
+}
+
+/// Split a protein sequence into tryptic segments (cleaving after K/R, but not
+/// before P). Each segment except possibly the last ends with the cleavage site
+fn tryptic_segments(sequence: &[u8]) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for idx in 0..sequence.len() {
+        let cleave = matches!(sequence[idx], b'K' | b'R') && sequence.get(idx + 1) != Some(&b'P');
+        if cleave {
+            segments.push(&sequence[start..=idx]);
+            start = idx + 1;
+        }
+    }
+    if start < sequence.len() {
+        segments.push(&sequence[start..]);
+    }
+    segments
+}
+
+/// Generate a decoy protein sequence. Returns `None` for [`DecoyStrategy::Reverse`],
+/// which is handled by sage itself
+fn decoy_sequence(sequence: &str, strategy: DecoyStrategy, de_bruijn: &[u8]) -> Option<String> {
+    let mut decoy = Vec::with_capacity(sequence.len());
+    for segment in tryptic_segments(sequence.as_bytes()) {
+        // Keep the C-terminal cleavage site fixed
+        let (body, fixed) = match segment.last() {
+            Some(b'K') | Some(b'R') => segment.split_at(segment.len() - 1),
+            _ => (segment, &segment[segment.len()..]),
+        };
+        let mut body = body.to_vec();
+        match strategy {
+            DecoyStrategy::Reverse => return None,
+            DecoyStrategy::PseudoReverse => body.reverse(),
+            DecoyStrategy::Shuffle(seed) => {
+                let mut rng = SplitMix64::for_segment(seed, segment);
+                for i in (1..body.len()).rev() {
+                    body.swap(i, rng.below(i + 1));
+                }
+            }
+            DecoyStrategy::DeBruijn(seed) => {
+                let start = SplitMix64::for_segment(seed, segment).below(de_bruijn.len());
+                for (i, residue) in body.iter_mut().enumerate() {
+                    *residue = de_bruijn[(start + i) % de_bruijn.len()];
+                }
+            }
+        }
+        decoy.extend(body);
+        decoy.extend_from_slice(fixed);
+    }
+    Some(String::from_utf8_lossy(&decoy).into_owned())
+}
+
+/// Generate decoy proteins for `(accession, sequence)` pairs, prefixing each
+/// accession with `decoy_tag`. Each tryptic segment is randomized with its own
+/// seed, derived from the strategy seed and the segment sequence
+pub fn generate(
+    proteins: &[(String, String)],
+    strategy: DecoyStrategy,
+    decoy_tag: &str,
+) -> Vec<(String, String)> {
+    let de_bruijn = de_bruijn();
+    proteins
+        .iter()
+        .filter_map(|(accession, sequence)| {
+            decoy_sequence(sequence, strategy, &de_bruijn)
+                .map(|decoy| (format!("{}{}", decoy_tag, accession), decoy))
+        })
+        .collect()
+}
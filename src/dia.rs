@@ -0,0 +1,266 @@
+use crate::annotate::annotate_peaks;
+use crate::lfq::{fragment_traces, isolation_window};
+use crate::psm::Psm;
+use crate::spectra::Mzml;
+use crate::Database;
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use sage_core::database::PeptideIx;
+use sage_core::mass::{Tolerance, PROTON};
+use sage_core::scoring::Scorer;
+use sage_core::spectrum::{Precursor, ProcessedSpectrum};
+use std::collections::{HashMap, HashSet};
+
+fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    match var_a > 0.0 && var_b > 0.0 {
+        true => cov / (var_a * var_b).sqrt(),
+        false => 0.0,
+    }
+}
+
+/// Mean correlation of each fragment trace with the sum of the others
+fn coelution(traces: &[Vec<f32>]) -> f32 {
+    if traces.len() < 2 || traces[0].len() < 3 {
+        return 0.0;
+    }
+    let total = (0..traces[0].len())
+        .map(|i| traces.iter().map(|t| t[i]).sum::<f32>())
+        .collect::<Vec<_>>();
+    traces
+        .iter()
+        .map(|trace| {
+            let rest = total
+                .iter()
+                .zip(trace)
+                .map(|(t, x)| t - x)
+                .collect::<Vec<_>>();
+            pearson(trace, &rest)
+        })
+        .sum::<f32>()
+        / traces.len() as f32
+}
+
+#[pyclass]
+pub struct DiaMatch {
+    /// Best scoring PSM, from the scan at the chromatographic apex
+    #[pyo3(get)]
+    pub psm: Py<Psm>,
+    /// Isolation window (m/z) that the precursor was identified in
+    #[pyo3(get)]
+    pub isolation_window: (f32, f32),
+    /// Number of consecutive cycles around the apex in which the peptide
+    /// was a candidate
+    #[pyo3(get)]
+    pub cycles: usize,
+    /// Fragment ion labels, e.g. y7
+    #[pyo3(get)]
+    pub fragments: Vec<String>,
+    /// Fragment ion m/z (singly charged)
+    #[pyo3(get)]
+    pub fragment_mz: Vec<f32>,
+    /// Retention times of the scans in the fragment XICs
+    #[pyo3(get)]
+    pub rt: Vec<f32>,
+    /// Fragment XICs, one trace per fragment, aligned on `rt`
+    #[pyo3(get)]
+    pub xics: Vec<Vec<f32>>,
+    /// Mean correlation of each fragment XIC with the sum of the others
+    #[pyo3(get)]
+    pub coelution: f32,
+}
+
+#[pymethods]
+impl DiaMatch {
+    fn __repr__(&self, py: Python) -> String {
+        let psm = self.psm.borrow(py);
+        format!(
+            "{}/{} rt={:.2} coelution={:.3}",
+            psm.peptide, psm.charge, psm.rt, self.coelution
+        )
+    }
+}
+
+/// A DIA identification, before handing the PSM over to Python
+struct Match {
+    psm: Psm,
+    isolation_window: (f32, f32),
+    cycles: usize,
+    fragments: Vec<String>,
+    fragment_mz: Vec<f32>,
+    rt: Vec<f32>,
+    xics: Vec<Vec<f32>>,
+    coelution: f32,
+}
+
+/// Candidate precursor from a single DIA scan
+struct Candidate {
+    scan: usize,
+    psm: Psm,
+}
+
+/// Spectrum-centric DIA search. Every MS2 scan is scored against all
+/// peptides whose precursor m/z falls within its isolation window (at each
+/// charge in `charges`). Candidates are then collected across consecutive
+/// cycles of the same window: the apex scan provides the PSM, and fragment
+/// XICs are extracted from the `cycles` scans on either side of it
+#[allow(clippy::too_many_arguments)]
+pub fn search(
+    py: Python,
+    db: &Database,
+    file: &Mzml,
+    charges: &[u8],
+    candidates: usize,
+    cycles: usize,
+    min_fragments: usize,
+    fragment: Tolerance,
+) -> PyResult<Vec<DiaMatch>> {
+    // Consecutive scans of each isolation window, in RT order
+    let mut windows: HashMap<(u32, u32), Vec<&ProcessedSpectrum>> = HashMap::new();
+    for spectrum in file.spectra.iter().filter(|s| s.level == 2) {
+        if let Some((lo, hi)) = isolation_window(spectrum) {
+            windows
+                .entry((lo.to_bits(), hi.to_bits()))
+                .or_default()
+                .push(spectrum);
+        }
+    }
+    for scans in windows.values_mut() {
+        scans.sort_by(|a, b| a.scan_start_time.total_cmp(&b.scan_start_time));
+    }
+
+    let mut best: HashMap<(PeptideIx, u8), Match> = HashMap::new();
+    for ((lo, hi), scans) in &windows {
+        let (lo, hi) = (f32::from_bits(*lo), f32::from_bits(*hi));
+        let (center, half) = ((lo + hi) / 2.0, (hi - lo) / 2.0);
+
+        let hits = scans
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(scan, spectrum)| {
+                charges.iter().flat_map(move |&charge| {
+                    // Treat the window as a single precursor at its center, with
+                    // a precursor tolerance spanning the whole window
+                    let z = charge as f32;
+                    let scorer = Scorer::new(
+                        &db.inner,
+                        Tolerance::Da(-half * z, half * z),
+                        fragment,
+                        0,
+                        0,
+                        Some(1),
+                        150.0,
+                        2000.0,
+                        false,
+                    );
+                    let query = ProcessedSpectrum {
+                        precursors: vec![Precursor {
+                            mz: center,
+                            intensity: None,
+                            charge: Some(charge),
+                            spectrum_ref: None,
+                            isolation_window: None,
+                        }],
+                        ..(*spectrum).clone()
+                    };
+                    scorer
+                        .score(&query, candidates)
+                        .into_iter()
+                        .map(move |feature| Candidate {
+                            scan,
+                            psm: feature.into(),
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut precursors: HashMap<(PeptideIx, u8), Vec<Candidate>> = HashMap::new();
+        for hit in hits {
+            if let Some(ix) = hit.psm.peptide_ix {
+                precursors
+                    .entry((ix, hit.psm.charge))
+                    .or_default()
+                    .push(hit);
+            }
+        }
+
+        for ((ix, charge), mut hits) in precursors {
+            hits.sort_by(|a, b| b.psm.hyperscore.total_cmp(&a.psm.hyperscore));
+            let apex = hits.swap_remove(0);
+            let start = apex.scan.saturating_sub(cycles);
+            let end = (apex.scan + cycles + 1).min(scans.len());
+            let support = 1 + hits
+                .iter()
+                .filter(|hit| hit.scan >= start && hit.scan < end && hit.scan != apex.scan)
+                .map(|hit| hit.scan)
+                .collect::<HashSet<_>>()
+                .len();
+
+            // Fragments matched in the apex scan. Peaks are deconvoluted to
+            // charge 1, so only singly charged fragments are considered
+            let mut matched =
+                annotate_peaks(scans[apex.scan], db.inner[ix].clone(), fragment, Some(2));
+            matched.sort_by(|a, b| b.intensity.total_cmp(&a.intensity));
+            let mut labels = Vec::new();
+            let mut masses = Vec::new();
+            for peak in matched {
+                let label = format!("{}{}", peak.ion, peak.index);
+                if !labels.contains(&label) && !masses.contains(&peak.mass) {
+                    labels.push(label);
+                    masses.push(peak.mass);
+                }
+            }
+            if masses.len() < min_fragments {
+                continue;
+            }
+
+            let xics = fragment_traces(&scans[start..end], &masses, fragment);
+            let entry = Match {
+                psm: apex.psm,
+                isolation_window: (lo, hi),
+                cycles: support,
+                fragments: labels,
+                fragment_mz: masses.iter().map(|m| m + PROTON).collect(),
+                rt: scans[start..end]
+                    .iter()
+                    .map(|s| s.scan_start_time)
+                    .collect(),
+                coelution: coelution(&xics),
+                xics,
+            };
+            // Overlapping windows can identify the same precursor twice
+            match best.get(&(ix, charge)) {
+                Some(m) if m.psm.hyperscore >= entry.psm.hyperscore => {}
+                _ => {
+                    best.insert((ix, charge), entry);
+                }
+            }
+        }
+    }
+
+    let mut matches = best.into_values().collect::<Vec<_>>();
+    matches.sort_by(|a, b| b.psm.hyperscore.total_cmp(&a.psm.hyperscore));
+    matches
+        .into_iter()
+        .map(|m| {
+            Ok(DiaMatch {
+                psm: Py::new(py, m.psm)?,
+                isolation_window: m.isolation_window,
+                cycles: m.cycles,
+                fragments: m.fragments,
+                fragment_mz: m.fragment_mz,
+                rt: m.rt,
+                xics: m.xics,
+                coelution: m.coelution,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,234 @@
+use crate::fasta;
+use pyo3::exceptions::{PyFileNotFoundError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use sage_core::database::Builder;
+use sage_core::enzyme::{EnzymeBuilder, EnzymeParameters};
+use sage_core::mass::{Mass, H2O, VALID_AA};
+use std::collections::HashMap;
+
+/// Proteins to digest: a path to a FASTA file, a dictionary of accessions
+/// to sequences, or a list of sequences (used as their own accessions)
+#[derive(FromPyObject)]
+pub enum Proteins {
+    Fasta(String),
+    Map(HashMap<String, String>),
+    Sequences(Vec<String>),
+}
+
+impl Proteins {
+    fn load(self) -> PyResult<Vec<(String, String)>> {
+        let mut proteins = match self {
+            Proteins::Fasta(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|_| PyErr::new::<PyFileNotFoundError, _>(path))?;
+                return Ok(fasta::parse(&contents));
+            }
+            Proteins::Map(map) => map.into_iter().collect::<Vec<_>>(),
+            Proteins::Sequences(sequences) => {
+                sequences.into_iter().map(|s| (s.clone(), s)).collect()
+            }
+        };
+        proteins.sort();
+        Ok(proteins)
+    }
+}
+
+/// Digestion settings, resolved through sage's [`Builder`] so that unset
+/// options take the same defaults as a [`crate::Database`]
+struct Enzyme(EnzymeParameters);
+
+impl Enzyme {
+    fn new(
+        cleave_at: Option<String>,
+        restrict: Option<char>,
+        missed_cleavages: Option<u8>,
+        min_len: Option<usize>,
+        max_len: Option<usize>,
+    ) -> Self {
+        let builder = Builder {
+            enzyme: Some(EnzymeBuilder {
+                missed_cleavages,
+                min_len,
+                max_len,
+                cleave_at,
+                restrict,
+            }),
+            ..Default::default()
+        };
+        Enzyme(builder.make_parameters().enzyme)
+    }
+
+    /// Digest a single protein, returning `(start, end, missed cleavages,
+    /// semi)`. Cleavage sites are sage's; semi-enzymatic peptides keep one
+    /// of them as a terminus
+    fn cleave(&self, sequence: &str, semi: bool) -> Vec<(usize, usize, usize, bool)> {
+        let params = &self.0;
+        // Positions between residues where sage cleaves, including both termini
+        let mut sites = params
+            .cleavage_sites(sequence)
+            .into_iter()
+            .map(|range| range.start)
+            .chain([0, sequence.len()])
+            .collect::<Vec<_>>();
+        sites.sort_unstable();
+        sites.dedup();
+
+        let is_site = |i: usize| sites.binary_search(&i).is_ok();
+        let missed = |start: usize, end: usize| {
+            sites.partition_point(|&s| s < end) - sites.partition_point(|&s| s <= start)
+        };
+        let max_missed = params.missed_cleavages as usize;
+        let len_ok = |start: usize, end: usize| {
+            (params.min_len.max(1)..=params.max_len).contains(&(end - start))
+        };
+
+        let mut peptides = Vec::new();
+        for (idx, &start) in sites.iter().enumerate() {
+            for &end in sites.iter().skip(idx + 1).take(max_missed + 1) {
+                if len_ok(start, end) {
+                    peptides.push((start, end, missed(start, end), false));
+                }
+            }
+        }
+
+        if semi {
+            for &site in &sites {
+                let ends = site + 1..=(site + params.max_len).min(sequence.len());
+                let starts = site.saturating_sub(params.max_len)..site;
+                let candidates = ends
+                    .map(|end| (site, end, end))
+                    .chain(starts.map(|start| (start, site, start)));
+                for (start, end, trimmed) in candidates {
+                    if is_site(trimmed) || !len_ok(start, end) {
+                        continue;
+                    }
+                    let missed = missed(start, end);
+                    if missed <= max_missed {
+                        peptides.push((start, end, missed, true));
+                    }
+                }
+            }
+        }
+        peptides.sort_unstable();
+        peptides.dedup();
+        peptides
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct DigestedPeptide {
+    #[pyo3(get)]
+    pub sequence: String,
+    /// Accession of the protein this peptide was digested from
+    #[pyo3(get)]
+    pub protein: String,
+    /// 0-based start position in the protein sequence
+    #[pyo3(get)]
+    pub start: usize,
+    /// 0-based, exclusive end position in the protein sequence
+    #[pyo3(get)]
+    pub end: usize,
+    /// Residue preceding the peptide, or '-' at the protein N-terminus
+    #[pyo3(get)]
+    pub previous_aa: char,
+    /// Residue following the peptide, or '-' at the protein C-terminus
+    #[pyo3(get)]
+    pub next_aa: char,
+    #[pyo3(get)]
+    pub missed_cleavages: u8,
+    /// True if only one terminus is an enzymatic cleavage site
+    #[pyo3(get)]
+    pub semi_enzymatic: bool,
+    /// Unmodified neutral monoisotopic mass
+    #[pyo3(get)]
+    pub monoisotopic: f32,
+}
+
+#[pymethods]
+impl DigestedPeptide {
+    fn __repr__(&self) -> String {
+        format!(
+            "{}.{}.{} {}[{}..{}]",
+            self.previous_aa, self.sequence, self.next_aa, self.protein, self.start, self.end
+        )
+    }
+}
+
+/// Uppercase a protein sequence, which must only contain ASCII letters
+fn normalize(accession: &str, sequence: &str) -> PyResult<String> {
+    let sequence = sequence.trim().to_ascii_uppercase();
+    match sequence.chars().find(|c| !c.is_ascii_alphabetic()) {
+        Some(c) => Err(PyErr::new::<PyValueError, _>(format!(
+            "protein {} contains invalid character {:?}",
+            accession, c
+        ))),
+        None => Ok(sequence),
+    }
+}
+
+/// digest(proteins, /, cleave_at, restrict, missed_cleavages, min_len, max_len, semi)
+/// --
+/// In-silico digestion of `proteins` (a FASTA path, a dictionary of accessions
+/// to sequences, or a list of sequences) with sage's enzyme, without building
+/// a fragment index. The enzyme cleaves after any residue in `cleave_at`,
+/// unless followed by `restrict`; unset options default to those used when
+/// building a `Database` (trypsin)
+#[pyfunction(semi = "false")]
+pub fn digest(
+    proteins: Proteins,
+    cleave_at: Option<String>,
+    restrict: Option<char>,
+    missed_cleavages: Option<u8>,
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    semi: Option<bool>,
+) -> PyResult<Vec<DigestedPeptide>> {
+    let enzyme = Enzyme::new(cleave_at, restrict, missed_cleavages, min_len, max_len);
+    let semi = semi.unwrap_or(false);
+    let proteins = proteins
+        .load()?
+        .into_iter()
+        .map(|(accession, sequence)| {
+            let sequence = normalize(&accession, &sequence)?;
+            Ok((accession, sequence))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(proteins
+        .par_iter()
+        .flat_map_iter(|(accession, sequence)| {
+            let bytes = sequence.as_bytes();
+            enzyme
+                .cleave(sequence, semi)
+                .into_iter()
+                // Skip peptides containing ambiguous residues, e.g. X or B
+                .filter(move |(start, end, _, _)| {
+                    bytes[*start..*end]
+                        .iter()
+                        .all(|&b| VALID_AA.contains(&(b as char)))
+                })
+                .map(move |(start, end, missed, semi_enzymatic)| {
+                    let peptide = &bytes[start..end];
+                    DigestedPeptide {
+                        sequence: String::from_utf8_lossy(peptide).into_owned(),
+                        protein: accession.clone(),
+                        start,
+                        end,
+                        previous_aa: start
+                            .checked_sub(1)
+                            .map(|i| bytes[i] as char)
+                            .unwrap_or('-'),
+                        next_aa: bytes.get(end).map(|&b| b as char).unwrap_or('-'),
+                        missed_cleavages: missed as u8,
+                        semi_enzymatic,
+                        monoisotopic: peptide
+                            .iter()
+                            .map(|&b| (b as char).monoisotopic())
+                            .sum::<f32>()
+                            + H2O,
+                    }
+                })
+        })
+        .collect())
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Format `(accession, sequence)` pairs as FASTA records
+pub fn format(proteins: &[(String, String)]) -> String {
+    let mut fasta = String::new();
+    for (accession, sequence) in proteins {
+        fasta.push('>');
+        fasta.push_str(accession);
+        fasta.push('\n');
+        for line in sequence.as_bytes().chunks(60) {
+            fasta.push_str(&String::from_utf8_lossy(line));
+            fasta.push('\n');
+        }
+    }
+    fasta
+}
+
+/// A FASTA file in the system temporary directory, deleted on drop.
+///
+/// `Parameters::build` (sage-core v0.7.1) only reads protein sequences from a
+/// path, so in-memory sequences are staged here for the lifetime of the
+/// database built from them
+pub struct TempFasta {
+    path: PathBuf,
+}
+
+impl TempFasta {
+    /// Write `proteins` to a new file, never reusing an existing path (e.g.
+    /// one left behind by another process with the same PID)
+    pub fn new(proteins: &[(String, String)]) -> std::io::Result<Self> {
+        loop {
+            let path = std::env::temp_dir().join(format!(
+                "sage_proteomics_{}_{}.fasta",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            // Remove the partial file if writing fails
+            let staged = Self { path };
+            file.write_all(format(proteins).as_bytes())?;
+            return Ok(staged);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFasta {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Parse FASTA records into `(accession, sequence)` pairs. The accession is
+/// the first whitespace-delimited token of the header line
+pub fn parse(contents: &str) -> Vec<(String, String)> {
+    let mut proteins = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('>') {
+            proteins.extend(current.take());
+            let accession = header.split_whitespace().next().unwrap_or_default();
+            current = Some((accession.to_string(), String::new()));
+        } else if let Some((_, sequence)) = current.as_mut() {
+            sequence.push_str(line);
+        }
+    }
+    proteins.extend(current);
+    proteins
+}
@@ -0,0 +1,26 @@
+/// Assign q-values by target-decoy competition. Each item is a `(score, decoy)`
+/// pair, where a higher score is better. Returned q-values are in the same
+/// order as the input
+pub fn q_values(items: &[(f64, bool)]) -> Vec<f32> {
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    order.sort_unstable_by(|&a, &b| items[b].0.total_cmp(&items[a].0));
+
+    let mut fdr = Vec::with_capacity(items.len());
+    let (mut targets, mut decoys) = (0usize, 0usize);
+    for &idx in &order {
+        match items[idx].1 {
+            true => decoys += 1,
+            false => targets += 1,
+        }
+        fdr.push(decoys as f32 / targets.max(1) as f32);
+    }
+
+    // Convert FDR to q-value: the minimum FDR at which a given item is accepted
+    let mut q_values = vec![1.0; items.len()];
+    let mut q_min = 1.0f32;
+    for (rank, &idx) in order.iter().enumerate().rev() {
+        q_min = q_min.min(fdr[rank]);
+        q_values[idx] = q_min;
+    }
+    q_values
+}
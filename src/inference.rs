@@ -0,0 +1,309 @@
+use crate::fdr;
+use crate::psm::{stripped_sequence, Psm};
+use pyo3::prelude::*;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+
+/// A group of proteins that cannot be distinguished by the identified peptides
+#[pyclass]
+#[derive(Clone)]
+pub struct ProteinGroup {
+    /// Indistinguishable proteins, identified by exactly the same peptides
+    #[pyo3(get)]
+    pub proteins: Vec<String>,
+    /// Proteins identified by a strict subset of this group's peptides
+    #[pyo3(get)]
+    pub subset_proteins: Vec<String>,
+    /// Proteins whose peptides are all explained by this and other groups
+    #[pyo3(get)]
+    pub subsumable_proteins: Vec<String>,
+    /// All peptide sequences (unmodified) identified for this group
+    #[pyo3(get)]
+    pub peptides: Vec<String>,
+    /// Peptides that only map to this group
+    #[pyo3(get)]
+    pub unique_peptides: Vec<String>,
+    /// Shared peptides assigned to this group by the razor principle
+    #[pyo3(get)]
+    pub razor_peptides: Vec<String>,
+    /// Best hyperscore of any peptide in this group
+    #[pyo3(get)]
+    pub score: f64,
+    /// Is this a group of decoy proteins?
+    #[pyo3(get)]
+    pub decoy: bool,
+    /// Protein-level q-value, calculated by picked target-decoy competition
+    #[pyo3(get)]
+    pub q_value: f32,
+}
+
+#[pymethods]
+impl ProteinGroup {
+    fn __repr__(&self) -> String {
+        format!(
+            "{} [{} peptides, q={}]",
+            self.proteins.join(";"),
+            self.peptides.len(),
+            self.q_value
+        )
+    }
+}
+
+/// Candidate group during inference: a set of indistinguishable proteins
+struct Candidate {
+    proteins: Vec<String>,
+    peptides: BTreeSet<String>,
+}
+
+/// Parsimonious protein inference (Occam's razor) from a list of PSMs.
+///
+/// 1. Proteins identified by the same set of peptides are merged into
+///    indistinguishable groups
+/// 2. Groups whose peptides are a strict subset of another group's peptides
+///    are removed, and reported as subset proteins of the larger group
+/// 3. The minimal set of groups explaining all peptides is selected greedily;
+///    remaining groups are reported as subsumable proteins
+/// 4. Shared peptides are assigned to the selected group with the most
+///    peptides (razor peptides)
+/// 5. Protein q-values are calculated by picked target-decoy competition
+pub fn infer(psms: &[PyRef<Psm>], q_value_cutoff: f32, decoy_tag: &str) -> Vec<ProteinGroup> {
+    let q_values = fdr::q_values(
+        &psms
+            .iter()
+            .map(|psm| (psm.hyperscore, psm.decoy))
+            .collect::<Vec<_>>(),
+    );
+
+    // Best score of each peptide, and the proteins it maps to
+    let mut peptide_scores: HashMap<String, f64> = HashMap::new();
+    let mut protein_peptides: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for (psm, q) in psms.iter().zip(q_values) {
+        if q > q_value_cutoff {
+            continue;
+        }
+        let sequence = stripped_sequence(&psm.peptide);
+        let score = peptide_scores
+            .entry(sequence.clone())
+            .or_insert(psm.hyperscore);
+        *score = score.max(psm.hyperscore);
+        for protein in &psm.proteins {
+            protein_peptides
+                .entry(protein.clone())
+                .or_default()
+                .insert(sequence.clone());
+        }
+    }
+
+    // 1. Indistinguishable proteins
+    let mut indistinguishable: HashMap<BTreeSet<String>, Vec<String>> = HashMap::new();
+    for (protein, peptides) in protein_peptides {
+        indistinguishable.entry(peptides).or_default().push(protein);
+    }
+    let mut candidates = indistinguishable
+        .into_iter()
+        .map(|(peptides, mut proteins)| {
+            proteins.sort();
+            Candidate { proteins, peptides }
+        })
+        .collect::<Vec<_>>();
+    // Deterministic ordering: largest groups first
+    candidates.sort_by(|a, b| {
+        b.peptides
+            .len()
+            .cmp(&a.peptides.len())
+            .then_with(|| a.proteins.cmp(&b.proteins))
+    });
+
+    // Index of candidate groups containing each peptide
+    let mut peptide_groups: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, candidate) in candidates.iter().enumerate() {
+        for peptide in &candidate.peptides {
+            peptide_groups
+                .entry(peptide.as_str())
+                .or_default()
+                .push(idx);
+        }
+    }
+
+    // 2. Subset groups: attach to the first (largest) superset group
+    let mut subset_of = vec![None; candidates.len()];
+    for (idx, candidate) in candidates.iter().enumerate() {
+        let first = match candidate.peptides.iter().next() {
+            Some(peptide) => peptide.as_str(),
+            None => continue,
+        };
+        subset_of[idx] = peptide_groups[first].iter().copied().find(|&other| {
+            other != idx
+                && candidates[other].peptides.len() > candidate.peptides.len()
+                && candidate.peptides.is_subset(&candidates[other].peptides)
+        });
+    }
+
+    // 3. Greedy set cover over the remaining groups, with lazy re-evaluation
+    let mut covered: BTreeSet<&str> = BTreeSet::new();
+    let mut selected = vec![false; candidates.len()];
+    let mut heap = (0..candidates.len())
+        .filter(|&idx| subset_of[idx].is_none())
+        .map(|idx| (candidates[idx].peptides.len(), std::cmp::Reverse(idx)))
+        .collect::<BinaryHeap<_>>();
+    while let Some((count, std::cmp::Reverse(idx))) = heap.pop() {
+        let uncovered = candidates[idx]
+            .peptides
+            .iter()
+            .filter(|p| !covered.contains(p.as_str()))
+            .count();
+        if uncovered == 0 {
+            continue;
+        }
+        if uncovered < count {
+            heap.push((uncovered, std::cmp::Reverse(idx)));
+            continue;
+        }
+        selected[idx] = true;
+        covered.extend(candidates[idx].peptides.iter().map(String::as_str));
+    }
+
+    // Attach subset and subsumable proteins to the selected group sharing the
+    // most peptides with them
+    let mut subset_proteins = vec![Vec::new(); candidates.len()];
+    let mut subsumable_proteins = vec![Vec::new(); candidates.len()];
+    for idx in 0..candidates.len() {
+        if selected[idx] {
+            continue;
+        }
+        let proteins = candidates[idx].proteins.iter().cloned();
+        match subset_of[idx] {
+            Some(parent) if selected[parent] => subset_proteins[parent].extend(proteins),
+            _ => {
+                let parent = candidates[idx]
+                    .peptides
+                    .iter()
+                    .flat_map(|p| peptide_groups[p.as_str()].iter().copied())
+                    .filter(|&other| selected[other])
+                    .max_by_key(|&other| {
+                        (
+                            candidates[other]
+                                .peptides
+                                .intersection(&candidates[idx].peptides)
+                                .count(),
+                            std::cmp::Reverse(other),
+                        )
+                    });
+                match (parent, subset_of[idx].is_some()) {
+                    (Some(parent), true) => subset_proteins[parent].extend(proteins),
+                    (Some(parent), false) => subsumable_proteins[parent].extend(proteins),
+                    (None, _) => {}
+                }
+            }
+        }
+    }
+
+    // 4. Unique & razor peptides
+    let mut razor: HashMap<&str, usize> = HashMap::new();
+    let mut unique: HashMap<&str, usize> = HashMap::new();
+    for (peptide, groups) in &peptide_groups {
+        let groups = groups
+            .iter()
+            .copied()
+            .filter(|&idx| selected[idx])
+            .collect::<Vec<_>>();
+        match groups.as_slice() {
+            [] => {}
+            [idx] if peptide_groups[peptide].len() == 1 => {
+                unique.insert(*peptide, *idx);
+            }
+            _ => {
+                // Candidates are sorted by size, so the first group is the largest
+                razor.insert(*peptide, groups[0]);
+            }
+        }
+    }
+
+    let mut groups = candidates
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| selected[*idx])
+        .map(|(idx, candidate)| {
+            let select = |map: &HashMap<&str, usize>| {
+                candidate
+                    .peptides
+                    .iter()
+                    .filter(|p| map.get(p.as_str()) == Some(&idx))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            ProteinGroup {
+                proteins: candidate.proteins.clone(),
+                subset_proteins: subset_proteins[idx].clone(),
+                subsumable_proteins: subsumable_proteins[idx].clone(),
+                peptides: candidate.peptides.iter().cloned().collect(),
+                unique_peptides: select(&unique),
+                razor_peptides: select(&razor),
+                score: candidate
+                    .peptides
+                    .iter()
+                    .filter_map(|p| peptide_scores.get(p))
+                    .copied()
+                    .fold(f64::MIN, f64::max),
+                decoy: candidate
+                    .proteins
+                    .iter()
+                    .all(|protein| protein.starts_with(decoy_tag)),
+                q_value: 1.0,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // 5. Picked target-decoy competition: only the better scoring of a target
+    // group and its matching decoy group is retained for FDR calculation
+    let mut picked: HashMap<String, usize> = HashMap::new();
+    for (idx, group) in groups.iter().enumerate() {
+        let key = group
+            .proteins
+            .iter()
+            .map(|p| p.trim_start_matches(decoy_tag))
+            .collect::<Vec<_>>()
+            .join(";");
+        picked
+            .entry(key)
+            .and_modify(|best| {
+                if group.score > groups[*best].score {
+                    *best = idx;
+                }
+            })
+            .or_insert(idx);
+    }
+    let winners = picked.into_values().collect::<Vec<_>>();
+    let q_values = fdr::q_values(
+        &winners
+            .iter()
+            .map(|&idx| (groups[idx].score, groups[idx].decoy))
+            .collect::<Vec<_>>(),
+    );
+    for (idx, q) in winners.into_iter().zip(q_values) {
+        groups[idx].q_value = q;
+    }
+
+    groups.sort_by(|a, b| {
+        a.q_value
+            .total_cmp(&b.q_value)
+            .then_with(|| b.score.total_cmp(&a.score))
+    });
+    groups
+}
+
+/// infer_proteins(psms, /, q_value_cutoff, decoy_tag)
+/// --
+/// Parsimonious protein inference from PSMs passing `q_value_cutoff`,
+/// returning protein groups with picked target-decoy protein q-values
+#[pyfunction(q_value_cutoff = "0.01", decoy_tag = "\"rev_\".into()")]
+pub fn infer_proteins(
+    psms: Vec<PyRef<Psm>>,
+    q_value_cutoff: Option<f32>,
+    decoy_tag: Option<String>,
+) -> Vec<ProteinGroup> {
+    infer(
+        &psms,
+        q_value_cutoff.unwrap_or(0.01),
+        decoy_tag.as_deref().unwrap_or("rev_"),
+    )
+}
@@ -1,20 +1,44 @@
-use crate::spectra::Peak;
+use crate::align::RtAlignment;
+use crate::psm::Psm;
+use crate::spectra::{Mzml, Peak};
+use crate::stats::median;
+use crate::{fdr, tolerance, Bounds};
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use rayon::prelude::*;
-use sage_core::{database::binary_search_slice, mass::Tolerance, spectrum::ProcessedSpectrum};
+use sage_core::{
+    database::binary_search_slice,
+    mass::{Tolerance, NEUTRON, PROTON},
+    spectrum::ProcessedSpectrum,
+};
+use std::collections::{HashMap, HashSet};
 
 #[pyclass]
+#[derive(Copy, Clone)]
 pub struct Xic {
     #[pyo3(get)]
-    rt: f32,
+    pub rt: f32,
     #[pyo3(get)]
-    mass: f32,
+    pub mass: f32,
     #[pyo3(get)]
-    intensity: f32,
+    pub intensity: f32,
 }
 
-pub fn xic(spectra: &[ProcessedSpectrum], rt_min: f32, rt_max: f32, mass: f32) -> Vec<Xic> {
-    let (lo, hi) = Tolerance::Ppm(-5.0, 5.0).bounds(mass);
+#[pymethods]
+impl Xic {
+    fn __repr__(&self) -> String {
+        format!("rt={}, mass={}, int={}", self.rt, self.mass, self.intensity)
+    }
+}
+
+pub fn xic(
+    spectra: &[ProcessedSpectrum],
+    rt_min: f32,
+    rt_max: f32,
+    mass: f32,
+    tolerance: Tolerance,
+) -> Vec<Xic> {
+    let (lo, hi) = tolerance.bounds(mass);
 
     let window = binary_search_slice(
         &spectra,
@@ -38,3 +62,641 @@ pub fn xic(spectra: &[ProcessedSpectrum], rt_min: f32, rt_max: f32, mass: f32) -
         })
         .collect()
 }
+
+/// Extract one trace per isotope (M, M+1, M+2, ...) for a precursor at `charge`,
+/// where `mass` is the position of the monoisotopic peak
+pub fn isotope_xic(
+    spectra: &[ProcessedSpectrum],
+    rt_min: f32,
+    rt_max: f32,
+    mass: f32,
+    charge: u8,
+    isotopes: usize,
+    tolerance: Tolerance,
+) -> Vec<Vec<Xic>> {
+    let spacing = NEUTRON / charge.max(1) as f32;
+    (0..isotopes)
+        .into_par_iter()
+        .map(|isotope| {
+            xic(
+                spectra,
+                rt_min,
+                rt_max,
+                mass + isotope as f32 * spacing,
+                tolerance,
+            )
+        })
+        .collect()
+}
+
+/// Absolute m/z bounds of the isolation window of an MSn scan
+pub fn isolation_window(spectrum: &ProcessedSpectrum) -> Option<(f32, f32)> {
+    let precursor = spectrum.precursors.first()?;
+    precursor.isolation_window.map(|w| w.bounds(precursor.mz))
+}
+
+/// Intensity of the most intense peak within `tol` of each fragment mass,
+/// in each scan. Returns one trace per fragment
+pub fn fragment_traces(
+    scans: &[&ProcessedSpectrum],
+    masses: &[f32],
+    tol: Tolerance,
+) -> Vec<Vec<f32>> {
+    masses
+        .iter()
+        .map(|&mass| {
+            let (lo, hi) = tol.bounds(mass);
+            scans
+                .iter()
+                .map(|scan| {
+                    let start = scan.peaks.partition_point(|p| p.mass < lo);
+                    scan.peaks[start..]
+                        .iter()
+                        .take_while(|p| p.mass <= hi)
+                        .map(|p| p.intensity)
+                        .fold(0.0, f32::max)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Fragment ion chromatograms of a single precursor, on a shared RT axis
+#[pyclass]
+#[derive(Clone)]
+pub struct FragmentXic {
+    #[pyo3(get)]
+    pub precursor_mz: f32,
+    #[pyo3(get)]
+    pub fragment_mz: Vec<f32>,
+    /// Retention times of the MS2 scans whose isolation window covers the
+    /// precursor
+    #[pyo3(get)]
+    pub rt: Vec<f32>,
+    /// One trace per fragment, aligned on `rt`. Scans where a fragment was
+    /// not observed have zero intensity
+    #[pyo3(get)]
+    pub intensities: Vec<Vec<f32>>,
+}
+
+#[pymethods]
+impl FragmentXic {
+    fn __repr__(&self) -> String {
+        format!(
+            "FragmentXic(precursor_mz={}, fragments={}, scans={})",
+            self.precursor_mz,
+            self.fragment_mz.len(),
+            self.rt.len()
+        )
+    }
+}
+
+/// Extract fragment chromatograms from the MS2 scans between `rt_min` and
+/// `rt_max` whose isolation window covers `precursor_mz`. Fragments are given
+/// as singly charged m/z, to match the deconvoluted MS2 peaks
+pub fn fragment_xic(
+    spectra: &[ProcessedSpectrum],
+    rt_min: f32,
+    rt_max: f32,
+    precursor_mz: f32,
+    fragment_mz: Vec<f32>,
+    tolerance: Tolerance,
+) -> FragmentXic {
+    let window = binary_search_slice(
+        spectra,
+        |spec, rt| spec.scan_start_time.total_cmp(rt),
+        rt_min,
+        rt_max,
+    );
+    let scans = spectra[window.0..window.1]
+        .iter()
+        .filter(|s| s.level == 2 && s.scan_start_time >= rt_min && s.scan_start_time <= rt_max)
+        .filter(|s| {
+            matches!(isolation_window(s), Some((lo, hi)) if precursor_mz >= lo && precursor_mz <= hi)
+        })
+        .collect::<Vec<_>>();
+    let masses = fragment_mz.iter().map(|mz| mz - PROTON).collect::<Vec<_>>();
+    FragmentXic {
+        precursor_mz,
+        rt: scans.iter().map(|s| s.scan_start_time).collect(),
+        intensities: fragment_traces(&scans, &masses, tolerance),
+        fragment_mz,
+    }
+}
+
+/// A chromatographic peak detected in an extracted ion chromatogram
+#[pyclass]
+#[derive(Copy, Clone, Debug)]
+pub struct ChromatographicPeak {
+    /// Retention time of the (smoothed) peak apex
+    #[pyo3(get)]
+    pub apex_rt: f32,
+    /// Raw intensity at the peak apex
+    #[pyo3(get)]
+    pub apex_intensity: f32,
+    /// Retention time of the left peak boundary
+    #[pyo3(get)]
+    pub start_rt: f32,
+    /// Retention time of the right peak boundary
+    #[pyo3(get)]
+    pub end_rt: f32,
+    /// Area under the raw trace between `start_rt` and `end_rt`
+    #[pyo3(get)]
+    pub area: f32,
+    /// Full width at half maximum of the smoothed trace
+    #[pyo3(get)]
+    pub fwhm: f32,
+    /// Apex intensity divided by the median intensity outside of the peak
+    #[pyo3(get)]
+    pub signal_to_noise: f32,
+    /// Number of MS1 scans between the peak boundaries
+    #[pyo3(get)]
+    pub points: usize,
+}
+
+#[pymethods]
+impl ChromatographicPeak {
+    fn __repr__(&self) -> String {
+        format!(
+            "Peak rt={} [{}-{}], area={}, s/n={}",
+            self.apex_rt, self.start_rt, self.end_rt, self.area, self.signal_to_noise
+        )
+    }
+}
+
+/// Summed intensity of matching peaks for every MS1 scan within the RT window.
+/// Unlike [`xic`], scans without a matching peak are reported with zero
+/// intensity, so that the trace can be used for peak detection
+pub fn trace(
+    spectra: &[ProcessedSpectrum],
+    rt_min: f32,
+    rt_max: f32,
+    mass: f32,
+    tolerance: Tolerance,
+) -> Vec<(f32, f32)> {
+    let (lo, hi) = tolerance.bounds(mass);
+
+    let window = binary_search_slice(
+        spectra,
+        |spec, rt| spec.scan_start_time.total_cmp(rt),
+        rt_min,
+        rt_max,
+    );
+    spectra[window.0..window.1]
+        .par_iter()
+        .filter(|s| s.level == 1 && s.scan_start_time >= rt_min && s.scan_start_time <= rt_max)
+        .map(|spectrum| {
+            let intensity = spectrum
+                .peaks
+                .iter()
+                .filter(|peak| peak.mass >= lo && peak.mass <= hi)
+                .map(|peak| peak.intensity)
+                .sum::<f32>();
+            (spectrum.scan_start_time, intensity)
+        })
+        .collect()
+}
+
+/// Centered moving average with a window of `width` points, shrinking the
+/// window at the edges of the trace
+fn smooth(intensities: &[f32], width: usize) -> Vec<f32> {
+    let half = width / 2;
+    (0..intensities.len())
+        .map(|idx| {
+            let lo = idx.saturating_sub(half);
+            let hi = (idx + half + 1).min(intensities.len());
+            intensities[lo..hi].iter().sum::<f32>() / (hi - lo) as f32
+        })
+        .collect()
+}
+
+/// Linearly interpolate the retention time at which the trace crosses `level`
+/// between points `a` and `b`
+fn crossing(rts: &[f32], smoothed: &[f32], a: usize, b: usize, level: f32) -> f32 {
+    let (ya, yb) = (smoothed[a], smoothed[b]);
+    if (yb - ya).abs() <= f32::EPSILON {
+        return rts[a];
+    }
+    rts[a] + (level - ya) / (yb - ya) * (rts[b] - rts[a])
+}
+
+/// Walk outwards from `apex` until the smoothed trace reaches a local minimum
+/// or drops below `floor`, returning the (start, end) indices of the peak
+fn boundaries(smoothed: &[f32], apex: usize, floor: f32) -> (usize, usize) {
+    let mut start = apex;
+    while start > 0 && smoothed[start - 1] <= smoothed[start] && smoothed[start] > floor {
+        start -= 1;
+    }
+    let mut end = apex;
+    while end + 1 < smoothed.len() && smoothed[end + 1] <= smoothed[end] && smoothed[end] > floor {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// Detect and integrate the dominant chromatographic peak in a trace, as
+/// produced by [`trace`].
+///
+/// The trace is smoothed with a moving average of `smoothing` points. If
+/// `target_rt` is supplied, the most intense peak whose boundaries contain
+/// `target_rt` is preferred, otherwise the most intense peak is reported.
+pub fn integrate(
+    trace: &[(f32, f32)],
+    target_rt: Option<f32>,
+    smoothing: usize,
+) -> Option<ChromatographicPeak> {
+    let rts = trace.iter().map(|(rt, _)| *rt).collect::<Vec<_>>();
+    let raw = trace.iter().map(|(_, int)| *int).collect::<Vec<_>>();
+    let smoothed = smooth(&raw, smoothing.max(1));
+
+    // Local maxima of the smoothed trace
+    let mut apexes = (0..smoothed.len())
+        .filter(|&idx| {
+            smoothed[idx] > 0.0
+                && (idx == 0 || smoothed[idx - 1] <= smoothed[idx])
+                && (idx + 1 == smoothed.len() || smoothed[idx + 1] < smoothed[idx])
+        })
+        .map(|apex| {
+            let (start, end) = boundaries(&smoothed, apex, smoothed[apex] * 0.05);
+            (apex, start, end)
+        })
+        .collect::<Vec<_>>();
+
+    apexes.sort_unstable_by(|a, b| smoothed[b.0].total_cmp(&smoothed[a.0]));
+    let (apex, start, end) = target_rt
+        .and_then(|rt| {
+            apexes
+                .iter()
+                .find(|(_, start, end)| rts[*start] <= rt && rts[*end] >= rt)
+        })
+        .or_else(|| apexes.first())
+        .copied()?;
+
+    let area = (start..end)
+        .map(|idx| (rts[idx + 1] - rts[idx]) * (raw[idx] + raw[idx + 1]) / 2.0)
+        .sum::<f32>();
+
+    let half = smoothed[apex] / 2.0;
+    let left = (start..apex)
+        .rev()
+        .find(|&idx| smoothed[idx] <= half)
+        .map(|idx| crossing(&rts, &smoothed, idx, idx + 1, half))
+        .unwrap_or(rts[start]);
+    let right = (apex + 1..=end)
+        .find(|&idx| smoothed[idx] <= half)
+        .map(|idx| crossing(&rts, &smoothed, idx - 1, idx, half))
+        .unwrap_or(rts[end]);
+
+    let outside = raw[..start]
+        .iter()
+        .chain(raw[end + 1..].iter())
+        .copied()
+        .filter(|&int| int > 0.0)
+        .collect::<Vec<_>>();
+    let noise = median(&outside)
+        .or_else(|| {
+            raw.iter()
+                .copied()
+                .filter(|&int| int > 0.0)
+                .min_by(|a, b| a.total_cmp(b))
+        })
+        .unwrap_or(1.0);
+
+    Some(ChromatographicPeak {
+        apex_rt: rts[apex],
+        apex_intensity: raw[apex],
+        start_rt: rts[start],
+        end_rt: rts[end],
+        area,
+        fwhm: right - left,
+        signal_to_noise: raw[apex] / noise,
+        points: end - start + 1,
+    })
+}
+
+/// Quantified MS1 signal for a single peptide precursor in one run
+#[pyclass]
+#[derive(Clone)]
+pub struct PrecursorQuant {
+    /// Peptide sequence, including modifications
+    #[pyo3(get)]
+    pub peptide: String,
+    /// Precursor charge
+    #[pyo3(get)]
+    pub charge: u8,
+    /// Proteins containing this peptide sequence
+    #[pyo3(get)]
+    pub proteins: Vec<String>,
+    /// File the precursor was quantified in
+    #[pyo3(get)]
+    pub file: String,
+    /// Retention time of the best scoring PSM for this precursor
+    #[pyo3(get)]
+    pub rt: f32,
+    /// Integrated peak area, or `None` if no peak was detected
+    #[pyo3(get)]
+    pub intensity: Option<f32>,
+    /// Detected chromatographic peak
+    #[pyo3(get)]
+    pub peak: Option<ChromatographicPeak>,
+    /// Was this precursor transferred from another run (match-between-runs)?
+    #[pyo3(get)]
+    pub mbr: bool,
+}
+
+#[pymethods]
+impl PrecursorQuant {
+    fn __repr__(&self) -> String {
+        format!(
+            "{}/{}+ {}: {:?}{}",
+            self.peptide,
+            self.charge,
+            self.file,
+            self.intensity,
+            if self.mbr { " (MBR)" } else { "" }
+        )
+    }
+}
+
+/// Minimal information needed to quantify a precursor, extracted from a
+/// [`Psm`] so that it can be shared across threads
+#[derive(Clone)]
+pub(crate) struct Precursor {
+    pub peptide: String,
+    pub charge: u8,
+    pub proteins: Vec<String>,
+    pub rt: f32,
+    pub mass: f32,
+    pub hyperscore: f64,
+}
+
+/// Filter PSMs to those passing `q_value_cutoff` (target-decoy competition
+/// on hyperscore), keeping only the best scoring PSM for each peptide and
+/// charge state
+pub(crate) fn confident_precursors(psms: &[PyRef<Psm>], q_value_cutoff: f32) -> Vec<Precursor> {
+    let q_values = fdr::q_values(
+        &psms
+            .iter()
+            .map(|psm| (psm.hyperscore, psm.decoy))
+            .collect::<Vec<_>>(),
+    );
+
+    let mut best: HashMap<(String, u8), Precursor> = HashMap::new();
+    for (psm, q) in psms.iter().zip(q_values) {
+        if psm.decoy || q > q_value_cutoff {
+            continue;
+        }
+        let precursor = Precursor {
+            peptide: psm.peptide.clone(),
+            charge: psm.charge,
+            proteins: psm.proteins.clone(),
+            rt: psm.rt,
+            mass: psm.precursor_mass(psm.charge),
+            hyperscore: psm.hyperscore,
+        };
+        best.entry((psm.peptide.clone(), psm.charge))
+            .and_modify(|entry| {
+                if precursor.hyperscore > entry.hyperscore {
+                    *entry = precursor.clone();
+                }
+            })
+            .or_insert(precursor);
+    }
+
+    let mut precursors = best.into_values().collect::<Vec<_>>();
+    precursors.sort_unstable_by(|a, b| (&a.peptide, a.charge).cmp(&(&b.peptide, b.charge)));
+    precursors
+}
+
+/// Extract and integrate the MS1 trace of each precursor centered on `rt`
+pub(crate) fn quantify_precursors(
+    spectra: &[ProcessedSpectrum],
+    file: &str,
+    precursors: &[Precursor],
+    rt_tolerance: f32,
+    tolerance: Tolerance,
+    smoothing: usize,
+    mbr: bool,
+) -> Vec<PrecursorQuant> {
+    precursors
+        .par_iter()
+        .map(|precursor| {
+            let trace = trace(
+                spectra,
+                precursor.rt - rt_tolerance,
+                precursor.rt + rt_tolerance,
+                precursor.mass,
+                tolerance,
+            );
+            let peak = integrate(&trace, Some(precursor.rt), smoothing);
+            PrecursorQuant {
+                peptide: precursor.peptide.clone(),
+                charge: precursor.charge,
+                proteins: precursor.proteins.clone(),
+                file: file.to_string(),
+                rt: precursor.rt,
+                intensity: peak.map(|peak| peak.area),
+                peak,
+                mbr,
+            }
+        })
+        .collect()
+}
+
+/// quantify(mzml, psms, /, q_value_cutoff, rt_tolerance, ppm, da, smoothing)
+/// --
+/// Label-free quantification of every confident peptide precursor in a run.
+/// PSMs are filtered by target-decoy q-value and deduplicated by peptide and
+/// charge, then the MS1 peak of each precursor is extracted and integrated
+#[pyfunction(q_value_cutoff = "0.01", rt_tolerance = "2.5", smoothing = "3")]
+pub fn quantify(
+    mzml: &Mzml,
+    psms: Vec<PyRef<Psm>>,
+    q_value_cutoff: Option<f32>,
+    rt_tolerance: Option<f32>,
+    ppm: Option<Bounds>,
+    da: Option<Bounds>,
+    smoothing: Option<usize>,
+) -> PyResult<Vec<PrecursorQuant>> {
+    let precursors = confident_precursors(&psms, q_value_cutoff.unwrap_or(0.01));
+    Ok(quantify_precursors(
+        &mzml.spectra,
+        &mzml.file,
+        &precursors,
+        rt_tolerance.unwrap_or(2.5),
+        tolerance(ppm, da, Tolerance::Ppm(-5.0, 5.0))?,
+        smoothing.unwrap_or(3),
+        false,
+    ))
+}
+
+/// Align every run onto the run with the most confident precursors, using
+/// the retention times of precursors identified in both runs
+pub(crate) fn align_precursors(
+    files: &[String],
+    runs: &[Vec<Precursor>],
+    segments: usize,
+) -> Vec<RtAlignment> {
+    let reference = (0..runs.len())
+        .max_by_key(|&idx| runs[idx].len())
+        .unwrap_or_default();
+
+    let reference_rt = runs
+        .get(reference)
+        .map(|precursors| {
+            precursors
+                .iter()
+                .map(|p| ((p.peptide.as_str(), p.charge), p.rt))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    runs.iter()
+        .zip(files)
+        .enumerate()
+        .map(|(idx, (precursors, file))| {
+            if idx == reference {
+                return RtAlignment::identity(file.clone());
+            }
+            let pairs = precursors
+                .iter()
+                .filter_map(|p| {
+                    reference_rt
+                        .get(&(p.peptide.as_str(), p.charge))
+                        .map(|&rt| (p.rt, rt))
+                })
+                .collect();
+            RtAlignment::fit(file.clone(), files[reference].clone(), pairs, segments)
+        })
+        .collect()
+}
+
+pub(crate) fn check_runs(runs: &[PyRef<Mzml>], psms: &[Vec<PyRef<Psm>>]) -> PyResult<()> {
+    if runs.len() != psms.len() {
+        return Err(PyErr::new::<PyValueError, _>(format!(
+            "expected one list of PSMs per run, got {} runs and {} lists",
+            runs.len(),
+            psms.len()
+        )));
+    }
+    Ok(())
+}
+
+/// align(runs, psms, /, q_value_cutoff, segments)
+/// --
+/// Fit a piecewise linear retention time alignment of each run onto the run
+/// with the most confident precursors. `psms[i]` are the search results of `runs[i]`
+#[pyfunction(q_value_cutoff = "0.01", segments = "10")]
+pub fn align(
+    runs: Vec<PyRef<Mzml>>,
+    psms: Vec<Vec<PyRef<Psm>>>,
+    q_value_cutoff: Option<f32>,
+    segments: Option<usize>,
+) -> PyResult<Vec<RtAlignment>> {
+    check_runs(&runs, &psms)?;
+    let q_value_cutoff = q_value_cutoff.unwrap_or(0.01);
+    let files = runs.iter().map(|run| run.file.clone()).collect::<Vec<_>>();
+    let precursors = psms
+        .iter()
+        .map(|psms| confident_precursors(psms, q_value_cutoff))
+        .collect::<Vec<_>>();
+    Ok(align_precursors(
+        &files,
+        &precursors,
+        segments.unwrap_or(10),
+    ))
+}
+
+/// match_between_runs(runs, psms, /, q_value_cutoff, rt_tolerance, mbr_rt_tolerance, ppm, da, smoothing, segments)
+/// --
+/// Label-free quantification across multiple runs with match-between-runs.
+/// Confident precursors are quantified in the runs where they were identified;
+/// precursors missing from a run are transferred from the run with the best
+/// scoring identification, using retention time alignment to predict where the
+/// precursor elutes. Transferred quant values are flagged with `mbr = True`
+#[pyfunction(
+    q_value_cutoff = "0.01",
+    rt_tolerance = "2.5",
+    mbr_rt_tolerance = "0.5",
+    smoothing = "3",
+    segments = "10"
+)]
+#[allow(clippy::too_many_arguments)]
+pub fn match_between_runs(
+    runs: Vec<PyRef<Mzml>>,
+    psms: Vec<Vec<PyRef<Psm>>>,
+    q_value_cutoff: Option<f32>,
+    rt_tolerance: Option<f32>,
+    mbr_rt_tolerance: Option<f32>,
+    ppm: Option<Bounds>,
+    da: Option<Bounds>,
+    smoothing: Option<usize>,
+    segments: Option<usize>,
+) -> PyResult<Vec<PrecursorQuant>> {
+    check_runs(&runs, &psms)?;
+    let q_value_cutoff = q_value_cutoff.unwrap_or(0.01);
+    let tolerance = tolerance(ppm, da, Tolerance::Ppm(-5.0, 5.0))?;
+    let smoothing = smoothing.unwrap_or(3);
+
+    let files = runs.iter().map(|run| run.file.clone()).collect::<Vec<_>>();
+    let precursors = psms
+        .iter()
+        .map(|psms| confident_precursors(psms, q_value_cutoff))
+        .collect::<Vec<_>>();
+    let alignments = align_precursors(&files, &precursors, segments.unwrap_or(10));
+
+    // Best scoring identification of each precursor across all runs, with its
+    // retention time mapped onto the reference run
+    let mut donors: HashMap<(String, u8), (Precursor, f32)> = HashMap::new();
+    for (run, alignment) in precursors.iter().zip(&alignments) {
+        for precursor in run {
+            let rt = alignment.to_reference(precursor.rt);
+            donors
+                .entry((precursor.peptide.clone(), precursor.charge))
+                .and_modify(|entry| {
+                    if precursor.hyperscore > entry.0.hyperscore {
+                        *entry = (precursor.clone(), rt);
+                    }
+                })
+                .or_insert_with(|| (precursor.clone(), rt));
+        }
+    }
+
+    let mut quant = Vec::new();
+    for ((run, identified), alignment) in runs.iter().zip(&precursors).zip(&alignments) {
+        quant.extend(quantify_precursors(
+            &run.spectra,
+            &run.file,
+            identified,
+            rt_tolerance.unwrap_or(2.5),
+            tolerance,
+            smoothing,
+            false,
+        ));
+
+        let present = identified
+            .iter()
+            .map(|p| (p.peptide.as_str(), p.charge))
+            .collect::<HashSet<_>>();
+        let mut transferred = donors
+            .iter()
+            .filter(|((peptide, charge), _)| !present.contains(&(peptide.as_str(), *charge)))
+            .map(|(_, (donor, reference_rt))| Precursor {
+                rt: alignment.onto_run(*reference_rt),
+                ..donor.clone()
+            })
+            .collect::<Vec<_>>();
+        transferred.sort_unstable_by(|a, b| (&a.peptide, a.charge).cmp(&(&b.peptide, b.charge)));
+
+        quant.extend(quantify_precursors(
+            &run.spectra,
+            &run.file,
+            &transferred,
+            mbr_rt_tolerance.unwrap_or(0.5),
+            tolerance,
+            smoothing,
+            true,
+        ));
+    }
+    Ok(quant)
+}
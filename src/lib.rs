@@ -1,69 +1,554 @@
 use annotate::AnnotatedPeak;
+use decoy::DecoyStrategy;
 use psm::Psm;
-use pyo3::exceptions::{PyFileNotFoundError, PyValueError};
+use pyo3::exceptions::{PyFileNotFoundError, PyIOError, PyIndexError, PyValueError};
 use pyo3::prelude::*;
+use rayon::prelude::*;
 use sage_core::database::{Builder, IndexedDatabase, Parameters};
+use sage_core::enzyme::EnzymeBuilder;
 // use sage_core::fasta::Digest;
 use sage_core::mass::{Mass, Residue, Tolerance};
 use sage_core::peptide::Peptide;
 use sage_core::scoring::Scorer;
-use std::collections::HashMap;
+use sage_core::spectrum::ProcessedSpectrum;
+use std::collections::{HashMap, HashSet};
 
+mod align;
 mod annotate;
+#[cfg(feature = "pyarrow")]
+mod columnar;
+mod decoy;
+mod dia;
+mod digest;
+mod fasta;
+mod fdr;
+mod inference;
 mod lfq;
+mod library;
+mod mass;
+mod mods;
+mod open_search;
+mod output;
+mod peptide;
+mod prediction;
 mod psm;
+mod recalibrate;
+mod retention;
+mod rollup;
 mod spectra;
+mod stats;
+mod targeted;
+mod tmt;
+mod unimod;
 
 /// Python bindings to the Sage proteomic search engine
 #[pymodule]
 fn sage_proteomics(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Database>()?;
     m.add_class::<psm::Psm>()?;
+    m.add_class::<peptide::Peptide>()?;
     m.add_class::<annotate::AnnotatedPeak>()?;
+    m.add_class::<inference::ProteinGroup>()?;
+    m.add_function(wrap_pyfunction!(inference::infer_proteins, m)?)?;
+    m.add_class::<dia::DiaMatch>()?;
+    m.add_class::<library::SpectralLibrary>()?;
+    m.add_class::<library::LibrarySpectrum>()?;
+    m.add_class::<library::LibraryPeak>()?;
+    m.add_class::<digest::DigestedPeptide>()?;
+    m.add_class::<prediction::Predictions>()?;
+    m.add_class::<retention::RtModel>()?;
+    m.add_class::<recalibrate::MassCalibration>()?;
+    m.add_function(wrap_pyfunction!(retention::fit_rt_model, m)?)?;
+    m.add_function(wrap_pyfunction!(digest::digest, m)?)?;
+    m.add_class::<open_search::MassShift>()?;
+    m.add_function(wrap_pyfunction!(open_search::mass_shifts, m)?)?;
+    m.add_function(wrap_pyfunction!(output::write_tsv, m)?)?;
+    #[cfg(feature = "pyarrow")]
+    m.add_function(wrap_pyfunction!(columnar::psms_to_arrow, m)?)?;
+    #[cfg(feature = "pyarrow")]
+    m.add_function(wrap_pyfunction!(columnar::write_parquet, m)?)?;
     let spectra_module = PyModule::new(_py, "spectra")?;
     spectra_module.add_class::<spectra::Mzml>()?;
     spectra_module.add_class::<spectra::Spectrum>()?;
     spectra_module.add_class::<spectra::Peak>()?;
     spectra_module.add_class::<spectra::Precursor>()?;
     m.add_submodule(spectra_module)?;
+    let lfq_module = PyModule::new(_py, "lfq")?;
+    lfq_module.add_class::<lfq::Xic>()?;
+    lfq_module.add_class::<lfq::ChromatographicPeak>()?;
+    lfq_module.add_class::<lfq::FragmentXic>()?;
+    lfq_module.add_class::<lfq::PrecursorQuant>()?;
+    lfq_module.add_class::<align::RtAlignment>()?;
+    lfq_module.add_function(wrap_pyfunction!(lfq::quantify, lfq_module)?)?;
+    lfq_module.add_function(wrap_pyfunction!(lfq::align, lfq_module)?)?;
+    lfq_module.add_function(wrap_pyfunction!(lfq::match_between_runs, lfq_module)?)?;
+    lfq_module.add_class::<rollup::ProteinMatrix>()?;
+    lfq_module.add_function(wrap_pyfunction!(rollup::protein_rollup, lfq_module)?)?;
+    m.add_submodule(lfq_module)?;
+    let tmt_module = PyModule::new(_py, "tmt")?;
+    tmt_module.add_class::<tmt::ReporterIons>()?;
+    tmt_module.add_function(wrap_pyfunction!(tmt::attach_reporter_ions, tmt_module)?)?;
+    m.add_submodule(tmt_module)?;
+    let mass_module = PyModule::new(_py, "mass")?;
+    mass_module.add_class::<mass::Fragment>()?;
+    mass_module.add_function(wrap_pyfunction!(mass::mz, mass_module)?)?;
+    mass_module.add_function(wrap_pyfunction!(mass::ppm, mass_module)?)?;
+    mass_module.add_function(wrap_pyfunction!(mass::peptide_mass, mass_module)?)?;
+    mass_module.add_function(wrap_pyfunction!(mass::fragment_ladder, mass_module)?)?;
+    mass_module.add_function(wrap_pyfunction!(mass::isotope_envelope, mass_module)?)?;
+    m.add_submodule(mass_module)?;
+    let targeted_module = PyModule::new(_py, "targeted")?;
+    targeted_module.add_class::<targeted::Transition>()?;
+    targeted_module.add_class::<targeted::TargetedQuant>()?;
+    targeted_module.add_function(wrap_pyfunction!(
+        targeted::read_transitions,
+        targeted_module
+    )?)?;
+    targeted_module.add_function(wrap_pyfunction!(targeted::quantify, targeted_module)?)?;
+    m.add_submodule(targeted_module)?;
     Ok(())
 }
 
+/// Tolerance bounds passed from Python: either a single value `x` (interpreted
+/// as `(-x, x)`), or an explicit `(lo, hi)` tuple
+#[derive(FromPyObject, Copy, Clone)]
+pub enum Bounds {
+    Symmetric(f32),
+    Asymmetric(f32, f32),
+}
+
+impl Bounds {
+    fn range(self) -> (f32, f32) {
+        match self {
+            Bounds::Symmetric(x) => (-x.abs(), x.abs()),
+            Bounds::Asymmetric(lo, hi) => (lo, hi),
+        }
+    }
+}
+
+/// Build a [`Tolerance`] from optional ppm or Da bounds, falling back to `default`
+/// if neither is supplied
+pub fn tolerance(
+    ppm: Option<Bounds>,
+    da: Option<Bounds>,
+    default: Tolerance,
+) -> PyResult<Tolerance> {
+    match (ppm, da) {
+        (Some(_), Some(_)) => Err(PyErr::new::<PyValueError, _>(
+            "only one of `ppm` or `da` tolerance may be specified",
+        )),
+        (Some(ppm), None) => {
+            let (lo, hi) = ppm.range();
+            Ok(Tolerance::Ppm(lo, hi))
+        }
+        (None, Some(da)) => {
+            let (lo, hi) = da.range();
+            Ok(Tolerance::Da(lo, hi))
+        }
+        (None, None) => Ok(default),
+    }
+}
+
 #[pyclass]
-struct Database {
+pub struct Database {
     inner: IndexedDatabase,
     params: Parameters,
+    /// FASTA file staged for databases built from in-memory sequences, kept
+    /// for as long as `params` refers to it
+    staged: Option<fasta::TempFasta>,
+}
+
+fn validate_sequence(sequence: &str) -> PyResult<()> {
+    for c in sequence.chars() {
+        if !sage_core::mass::VALID_AA.contains(&c) {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "Sequence {} contains invalid amino acid {}",
+                sequence, c
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl Database {
+    fn build(
+        fasta: &str,
+        mut builder: Builder,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        builder.update_fasta(fasta.into());
+        let params = builder.make_parameters();
+        let db = params.clone().build()?;
+        Ok(Self {
+            inner: db,
+            params,
+            staged: None,
+        })
+    }
+
+    /// Build a database from `(accession, sequence)` pairs. Unless sage's
+    /// built-in reversal is used, decoy proteins are generated here and
+    /// written alongside the targets.
+    ///
+    /// sage-core v0.7.1 only reads proteins from a FASTA path, so the
+    /// sequences are staged in a temporary file that lives as long as the
+    /// database: this requires a writable temporary directory
+    fn build_in_memory(
+        mut proteins: Vec<(String, String)>,
+        strategy: DecoyStrategy,
+        mut builder: Builder,
+    ) -> PyResult<Self> {
+        if strategy != DecoyStrategy::Reverse && builder.generate_decoys.unwrap_or(true) {
+            let decoy_tag = builder.decoy_tag.as_deref().unwrap_or("rev_");
+            let decoys = decoy::generate(&proteins, strategy, decoy_tag);
+            for (_, sequence) in &decoys {
+                validate_sequence(sequence)?;
+            }
+            proteins.extend(decoys);
+            builder.generate_decoys = Some(false);
+        }
+        let fasta = fasta::TempFasta::new(&proteins).map_err(|e| {
+            PyErr::new::<PyIOError, _>(format!(
+                "unable to stage FASTA in {}: {}",
+                std::env::temp_dir().display(),
+                e
+            ))
+        })?;
+        let mut db = Self::build(&fasta.path().to_string_lossy(), builder)
+            .map_err(|e| PyErr::new::<PyValueError, _>(e.to_string()))?;
+        db.staged = Some(fasta);
+        Ok(db)
+    }
+
+    /// Configure a [`Scorer`] for this database. Precursor tolerance defaults
+    /// to +/- 20 ppm, or -150 to +500 Da for open searches, which also
+    /// disable isotope error correction. Fragment tolerance defaults to +/- 10 ppm
+    fn scorer(
+        &self,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+        open_search: bool,
+    ) -> PyResult<Scorer<'_>> {
+        let (precursor, isotope_errors) = match open_search {
+            true => {
+                let (lo, hi) = open_search::OPEN_SEARCH_DA;
+                (Tolerance::Da(lo, hi), (0, 0))
+            }
+            false => (Tolerance::Ppm(-20.0, 20.0), (-1, 3)),
+        };
+        Ok(Scorer::new(
+            &self.inner,
+            tolerance(precursor_ppm, precursor_da, precursor)?,
+            fragment_tolerance(fragment_ppm, fragment_da)?,
+            isotope_errors.0,
+            isotope_errors.1,
+            Some(1),
+            150.0,
+            2000.0,
+            false,
+        ))
+    }
+
+    /// Score `query`, returning up to `report_psms` PSMs. In chimera mode, the
+    /// fragment peaks explained by each PSM are subtracted before the next
+    /// candidate (of a different unmodified sequence) is scored against the
+    /// residual spectrum
+    #[allow(clippy::too_many_arguments)]
+    fn score(
+        &self,
+        query: &ProcessedSpectrum,
+        report_psms: usize,
+        chimera: bool,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+        open_search: bool,
+    ) -> PyResult<Vec<Psm>> {
+        let scorer = self.scorer(
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+            open_search,
+        )?;
+        if !chimera {
+            return Ok(scorer
+                .score(query, report_psms)
+                .into_iter()
+                .enumerate()
+                .map(|(rank, feature)| Psm {
+                    rank: rank as u32 + 1,
+                    ..Psm::from(feature)
+                })
+                .collect());
+        }
+
+        // Chimeric search: take the best candidate, subtract the fragment
+        // peaks it explains, and re-score what is left of the spectrum
+        let fragment = fragment_tolerance(fragment_ppm, fragment_da)?;
+        let intensity = |spectrum: &ProcessedSpectrum| {
+            spectrum
+                .peaks
+                .iter()
+                .map(|peak| peak.intensity)
+                .sum::<f32>()
+        };
+        let total = intensity(query);
+        let mut residual = query.clone();
+        let mut psms: Vec<Psm> = Vec::new();
+        let mut reported = HashSet::new();
+        while psms.len() < report_psms {
+            // Already reported peptides, including other modified forms of
+            // the same sequence, may still rank first on the residual
+            // spectrum, so ask for enough candidates to skip past them
+            let best = match scorer
+                .score(&residual, report_psms + psms.len())
+                .into_iter()
+                .find(|feature| !reported.contains(&psm::stripped_sequence(&feature.peptide)))
+            {
+                Some(best) => best,
+                None => break,
+            };
+
+            // Deconvoluted peaks are singly charged: annotate at least at
+            // fragment charge 1, even for singly charged precursors
+            let peptide = self.inner[best.peptide_idx].clone();
+            let charge = best.charge.max(2);
+            let matched = annotate::annotate_peaks(&residual, peptide, fragment, Some(charge))
+                .into_iter()
+                .map(|peak| peak.mass.to_bits())
+                .collect::<HashSet<_>>();
+
+            let mut psm: Psm = best.into();
+            psm.rank = psms.len() as u32 + 1;
+            reported.insert(psm::stripped_sequence(&psm.peptide));
+            if total > 0.0 {
+                psm.residual_score = (intensity(&residual) / total) as f64;
+            }
+            psms.push(psm);
+
+            if matched.is_empty() {
+                break;
+            }
+            residual
+                .peaks
+                .retain(|peak| !matched.contains(&peak.mass.to_bits()));
+        }
+        Ok(psms)
+    }
+
+    /// Score every MS2 spectrum in `spectra`, returning up to `report_psms`
+    /// PSMs per spectrum
+    fn score_all(
+        &self,
+        spectra: &[ProcessedSpectrum],
+        report_psms: usize,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+    ) -> PyResult<Vec<Psm>> {
+        let scorer = self.scorer(
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+            false,
+        )?;
+        Ok(spectra
+            .par_iter()
+            .filter(|spectrum| spectrum.level == 2)
+            .flat_map_iter(|spectrum| {
+                scorer
+                    .score(spectrum, report_psms)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(rank, feature)| Psm {
+                        rank: rank as u32 + 1,
+                        ..Psm::from(feature)
+                    })
+            })
+            .collect())
+    }
+}
+
+fn fragment_tolerance(ppm: Option<Bounds>, da: Option<Bounds>) -> PyResult<Tolerance> {
+    tolerance(ppm, da, Tolerance::Ppm(-10.0, 10.0))
 }
 
 #[pymethods]
 impl Database {
-    /// new(fasta, /, decoy_prefix, static_mods, variable_mods)
+    /// new(fasta, /, decoy_tag, generate_decoys, static_mods, variable_mods, decoy_strategy, decoy_seed)
     /// --
     /// Create a new Sage database
     #[new]
     // #[args(decoy_prefix="\"rev_\"", static_mods="{\"C\": 57.0215}")]
-    #[args(decoy_tag = "\"rev_\".into()", generate_decoys = "true")]
+    #[args(
+        decoy_tag = "\"rev_\".into()",
+        generate_decoys = "true",
+        decoy_strategy = "\"reverse\".into()",
+        decoy_seed = "42"
+    )]
     fn new(
         fasta: &str,
         decoy_tag: Option<String>,
         generate_decoys: Option<bool>,
-        static_mods: Option<HashMap<char, f32>>,
-        variable_mods: Option<HashMap<char, f32>>,
+        static_mods: Option<mods::Mods>,
+        variable_mods: Option<mods::Mods>,
+        decoy_strategy: Option<String>,
+        decoy_seed: Option<u64>,
     ) -> PyResult<Self> {
-        let mut builder = Builder {
+        let (static_mods, variable_mods) = mods::resolve(static_mods, variable_mods)?;
+        let builder = Builder {
             decoy_tag,
             generate_decoys,
             static_mods,
             variable_mods,
             ..Default::default()
         };
-        builder.update_fasta(fasta.into());
-        let params = builder.make_parameters();
-        let db = params
-            .clone()
-            .build()
+        let strategy = DecoyStrategy::parse(
+            decoy_strategy.as_deref().unwrap_or("reverse"),
+            decoy_seed.unwrap_or(42),
+        )?;
+        if strategy == DecoyStrategy::Reverse {
+            return Self::build(fasta, builder)
+                .map_err(|e| PyErr::new::<PyFileNotFoundError, _>(fasta.to_string()));
+        }
+
+        let contents = std::fs::read_to_string(fasta)
             .map_err(|e| PyErr::new::<PyFileNotFoundError, _>(fasta.to_string()))?;
-        Ok(Self { inner: db, params })
+        Self::build_in_memory(fasta::parse(&contents), strategy, builder)
+    }
+
+    /// from_proteins(proteins, /, decoy_tag, generate_decoys, static_mods, variable_mods, decoy_strategy, decoy_seed)
+    /// --
+    /// Create a new Sage database from a dictionary of protein accessions
+    /// to sequences
+    #[staticmethod]
+    #[args(
+        decoy_tag = "\"rev_\".into()",
+        generate_decoys = "true",
+        decoy_strategy = "\"reverse\".into()",
+        decoy_seed = "42"
+    )]
+    fn from_proteins(
+        proteins: HashMap<String, String>,
+        decoy_tag: Option<String>,
+        generate_decoys: Option<bool>,
+        static_mods: Option<mods::Mods>,
+        variable_mods: Option<mods::Mods>,
+        decoy_strategy: Option<String>,
+        decoy_seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let (static_mods, variable_mods) = mods::resolve(static_mods, variable_mods)?;
+        let mut proteins = proteins.into_iter().collect::<Vec<_>>();
+        proteins.sort();
+        for (_, sequence) in &proteins {
+            validate_sequence(sequence)?;
+        }
+        Self::build_in_memory(
+            proteins,
+            DecoyStrategy::parse(
+                decoy_strategy.as_deref().unwrap_or("reverse"),
+                decoy_seed.unwrap_or(42),
+            )?,
+            Builder {
+                decoy_tag,
+                generate_decoys,
+                static_mods,
+                variable_mods,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// from_peptides(peptides, /, decoy_tag, generate_decoys, static_mods, variable_mods, decoy_strategy, decoy_seed)
+    /// --
+    /// Create a new Sage database from a list of peptide sequences. Each
+    /// peptide is treated as a protein, using the peptide sequence as accession.
+    /// Peptides are not cleaved, and the length and mass limits are widened to
+    /// admit every peptide given
+    #[staticmethod]
+    #[args(
+        decoy_tag = "\"rev_\".into()",
+        generate_decoys = "true",
+        decoy_strategy = "\"reverse\".into()",
+        decoy_seed = "42"
+    )]
+    fn from_peptides(
+        peptides: Vec<String>,
+        decoy_tag: Option<String>,
+        generate_decoys: Option<bool>,
+        static_mods: Option<mods::Mods>,
+        variable_mods: Option<mods::Mods>,
+        decoy_strategy: Option<String>,
+        decoy_seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let (static_mods, variable_mods) = mods::resolve(static_mods, variable_mods)?;
+        let mut peptides = peptides
+            .into_iter()
+            .map(|p| (p.clone(), p))
+            .collect::<Vec<_>>();
+        peptides.sort();
+        peptides.dedup();
+        for (_, sequence) in &peptides {
+            validate_sequence(sequence)?;
+        }
+        let lengths = peptides.iter().map(|(_, sequence)| sequence.len());
+        let enzyme = EnzymeBuilder {
+            missed_cleavages: Some(0),
+            min_len: lengths.clone().min(),
+            max_len: lengths.max(),
+            // sage's notation for "no cleavage"
+            cleave_at: Some("$".into()),
+            restrict: None,
+        };
+        Self::build_in_memory(
+            peptides,
+            DecoyStrategy::parse(
+                decoy_strategy.as_deref().unwrap_or("reverse"),
+                decoy_seed.unwrap_or(42),
+            )?,
+            Builder {
+                decoy_tag,
+                generate_decoys,
+                static_mods,
+                variable_mods,
+                enzyme: Some(enzyme),
+                // Any (modified) peptide mass, however small or large
+                peptide_min_mass: Some(0.0),
+                peptide_max_mass: Some(f32::MAX),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// decoy_collisions()
+    /// --
+    /// Return decoy peptide sequences that are identical to a target peptide
+    /// sequence (treating I and L as equivalent). Colliding decoys inflate
+    /// the estimated FDR, and indicate a poor decoy generation strategy
+    fn decoy_collisions(&self) -> Vec<String> {
+        let normalize = |p: &sage_core::peptide::Peptide| peptide::stripped(p).replace('I', "L");
+        let targets = self
+            .inner
+            .peptides
+            .par_iter()
+            .filter(|p| !p.decoy)
+            .map(normalize)
+            .collect::<HashSet<_>>();
+        let mut collisions = self
+            .inner
+            .peptides
+            .par_iter()
+            .filter(|p| p.decoy && targets.contains(&normalize(p)))
+            .map(peptide::stripped)
+            .collect::<Vec<_>>();
+        collisions.sort();
+        collisions.dedup();
+        collisions
     }
 
     #[getter]
@@ -72,6 +557,75 @@ impl Database {
         self.inner.fragments.len()
     }
 
+    #[getter]
+    /// Number of target peptides in database
+    fn targets(&self) -> usize {
+        self.inner.peptides.iter().filter(|p| !p.decoy).count()
+    }
+
+    #[getter]
+    /// Number of decoy peptides in database
+    fn decoys(&self) -> usize {
+        self.inner.peptides.iter().filter(|p| p.decoy).count()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.peptides.len()
+    }
+
+    /// peptides()
+    /// --
+    /// Iterate over all peptides in the database
+    fn peptides(slf: PyRef<'_, Self>) -> peptide::PeptideIter {
+        peptide::PeptideIter {
+            db: slf.into(),
+            index: 0,
+        }
+    }
+
+    /// get_peptide(index)
+    /// --
+    /// Return the peptide at `index`, e.g. from `Peptide.index`
+    fn get_peptide(&self, index: usize) -> PyResult<peptide::Peptide> {
+        peptide::Peptide::new(&self.inner, index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!(
+                "peptide index {} out of range for database with {} peptides",
+                index,
+                self.inner.peptides.len()
+            ))
+        })
+    }
+
+    /// find_peptide(sequence)
+    /// --
+    /// Return all modified forms of a peptide sequence (target and decoy)
+    /// present in the database. Modifications in `sequence` are ignored
+    fn find_peptide(&self, sequence: &str) -> Vec<peptide::Peptide> {
+        let sequence = psm::stripped_sequence(sequence);
+        self.inner
+            .peptides
+            .par_iter()
+            .enumerate()
+            .filter(|(_, p)| p.sequence.len() == sequence.len() && peptide::stripped(p) == sequence)
+            .filter_map(|(index, _)| peptide::Peptide::new(&self.inner, index))
+            .collect()
+    }
+
+    /// proteins_for(sequence)
+    /// --
+    /// Return the accessions of all proteins containing a peptide sequence.
+    /// Modifications in `sequence` are ignored
+    fn proteins_for(&self, sequence: &str) -> Vec<String> {
+        let mut proteins = self
+            .find_peptide(sequence)
+            .into_iter()
+            .flat_map(|p| p.proteins)
+            .collect::<Vec<_>>();
+        proteins.sort();
+        proteins.dedup();
+        proteins
+    }
+
     /// annotate_sequence(spectrum, sequence, / tolerance_ppm, charge, mods)
     /// --
     /// Annotate a MS2 spectrum with a provided peptide sequence
@@ -83,14 +637,7 @@ impl Database {
         charge: Option<u8>,
         mods: Option<HashMap<char, f32>>,
     ) -> PyResult<Vec<AnnotatedPeak>> {
-        for c in sequence.chars() {
-            if !sage_core::mass::VALID_AA.contains(&c) {
-                return Err(PyErr::new::<PyValueError, _>(format!(
-                    "Sequence {} contains invalid amino acid {}",
-                    sequence, c
-                )));
-            }
-        }
+        validate_sequence(&sequence)?;
         let query = &spectrum.into();
         let sequence = sequence
             .chars()
@@ -111,10 +658,11 @@ impl Database {
             }
         }
 
+        let tolerance = tolerance_ppm.unwrap_or(10.0);
         Ok(annotate::annotate_peaks(
             query,
             peptide,
-            tolerance_ppm.unwrap_or(10.0),
+            Tolerance::Ppm(-tolerance, tolerance),
             charge,
         ))
     }
@@ -128,20 +676,43 @@ impl Database {
         psm: &Psm,
         tolerance_ppm: Option<f32>,
         charge: Option<u8>,
-    ) -> Vec<AnnotatedPeak> {
+    ) -> PyResult<Vec<AnnotatedPeak>> {
         let query = &spectrum.into();
-        let peptide = self.inner[psm.peptide_ix].clone();
-        annotate::annotate_peaks(query, peptide, tolerance_ppm.unwrap_or(10.0), charge)
+        let peptide_ix = psm.peptide_ix.ok_or_else(|| {
+            PyErr::new::<PyValueError, _>(format!(
+                "PSM {} was not matched against a Database",
+                psm.peptide
+            ))
+        })?;
+        let peptide = self.inner[peptide_ix].clone();
+        let tolerance = tolerance_ppm.unwrap_or(10.0);
+        Ok(annotate::annotate_peaks(
+            query,
+            peptide,
+            Tolerance::Ppm(-tolerance, tolerance),
+            charge,
+        ))
     }
 
-    /// seach(spectrum, /, report_psms)
+    /// seach(spectrum, /, report_psms, precursor_ppm, precursor_da, fragment_ppm, fragment_da, open_search, chimera)
     /// --
-    /// Search and score a single MS2 spectra, returning `report_psms` PSM objects
-    #[args(report_psms = 1)]
+    /// Search and score a single MS2 spectra, returning `report_psms` PSM objects.
+    /// With `chimera`, up to `report_psms` co-fragmented peptides are reported,
+    /// each scored against the spectrum left after subtracting the peaks
+    /// matched by higher-ranked PSMs. `Psm.residual_score` is the fraction of
+    /// the spectrum's intensity that was left
+    #[args(report_psms = 1, open_search = "false", chimera = "false")]
+    #[allow(clippy::too_many_arguments)]
     fn search(
         &self,
         spectrum: spectra::Spectrum,
         report_psms: Option<usize>,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+        open_search: Option<bool>,
+        chimera: Option<bool>,
     ) -> PyResult<Vec<Psm>> {
         if spectrum.level != 2 {
             return Err(PyErr::new::<PyValueError, _>(format!(
@@ -149,56 +720,169 @@ impl Database {
                 spectrum.title, spectrum.level
             )));
         }
-        let query = &spectrum.into();
-        let scorer = Scorer::new(
-            &self.inner,
-            Tolerance::Ppm(-20.0, 20.0),
-            Tolerance::Ppm(-10.0, 10.0),
-            -1,
-            3,
-            Some(1),
-            150.0,
-            2000.0,
-            false,
-        );
-
-        Ok(scorer
-            .score(query, report_psms.unwrap_or(1))
-            .into_iter()
-            .map(Into::into)
-            .collect())
+        self.score(
+            &spectrum.into(),
+            report_psms.unwrap_or(1),
+            chimera.unwrap_or(false),
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+            open_search.unwrap_or(false),
+        )
     }
 
-    /// seach(file, scan, /, report_psms)
+    /// seach(file, scan, /, report_psms, precursor_ppm, precursor_da, fragment_ppm, fragment_da, open_search, chimera)
     /// --
     /// Search and score a single MS2 spectra from a given file and scan ID,
     /// returning `report_psms` PSM objects
+    #[args(report_psms = 1, open_search = "false", chimera = "false")]
+    #[allow(clippy::too_many_arguments)]
     fn search_by_id(
         &self,
         file: &spectra::Mzml,
         title: &str,
         report_psms: Option<usize>,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+        open_search: Option<bool>,
+        chimera: Option<bool>,
     ) -> PyResult<Vec<Psm>> {
         // let query = sage_core::spectrum::find_spectrum_by_id(&file.spectra, scan)
         // .ok_or(PyErr::new::<pyo3::exceptions::PyValueError, _>(scan))?;
         let query = file.get_sage_spectra(title)?;
+        self.score(
+            query,
+            report_psms.unwrap_or(1),
+            chimera.unwrap_or(false),
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+            open_search.unwrap_or(false),
+        )
+    }
 
-        let scorer = Scorer::new(
-            &self.inner,
-            Tolerance::Ppm(-20.0, 20.0),
-            Tolerance::Ppm(-10.0, 10.0),
-            -1,
-            3,
-            Some(1),
-            150.0,
-            2000.0,
-            false,
+    /// calibrate(file, psms, /, q_value_cutoff, fragment_ppm, fragment_da)
+    /// --
+    /// Fit the systematic precursor and fragment mass error (ppm) of `file`,
+    /// as a function of retention time and m/z, from its confident target
+    /// PSMs. Fragment errors are measured on the b- and y-ions matched within
+    /// the fragment tolerance
+    #[args(q_value_cutoff = "0.01")]
+    fn calibrate(
+        &self,
+        file: &spectra::Mzml,
+        psms: Vec<PyRef<Psm>>,
+        q_value_cutoff: Option<f32>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+    ) -> PyResult<recalibrate::MassCalibration> {
+        let psms = psms.iter().map(|psm| &**psm).collect::<Vec<_>>();
+        Ok(recalibrate::MassCalibration::fit(
+            self,
+            file,
+            &psms,
+            q_value_cutoff.unwrap_or(0.01),
+            fragment_tolerance(fragment_ppm, fragment_da)?,
+        ))
+    }
+
+    /// search_recalibrated(file, /, report_psms, q_value_cutoff, precursor_ppm, precursor_da, fragment_ppm, fragment_da)
+    /// --
+    /// Two-pass search of every MS2 spectrum in `file`. The first pass uses
+    /// the given tolerances; its confident PSMs are used to fit a mass
+    /// calibration, which is applied to `file` in place. The second pass then
+    /// searches the recalibrated spectra with tolerances derived from the
+    /// remaining mass error. Returns the second pass PSMs and the calibration
+    #[args(report_psms = 1, q_value_cutoff = "0.01")]
+    #[allow(clippy::too_many_arguments)]
+    fn search_recalibrated(
+        &self,
+        mut file: PyRefMut<spectra::Mzml>,
+        report_psms: Option<usize>,
+        q_value_cutoff: Option<f32>,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+    ) -> PyResult<(Vec<Psm>, recalibrate::MassCalibration)> {
+        let first = self.score_all(
+            &file.spectra,
+            1,
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+        )?;
+        let calibration = recalibrate::MassCalibration::fit(
+            self,
+            &file,
+            &first.iter().collect::<Vec<_>>(),
+            q_value_cutoff.unwrap_or(0.01),
+            fragment_tolerance(fragment_ppm, fragment_da)?,
         );
+        file.spectra
+            .par_iter_mut()
+            .for_each(|spectrum| calibration.correct(spectrum));
 
-        Ok(scorer
-            .score(query, report_psms.unwrap_or(1))
-            .into_iter()
-            .map(Into::into)
-            .collect())
+        // Fall back to the first pass tolerances if a model could not be fit
+        let (precursor, fragment) = calibration.tolerances();
+        let (precursor_ppm, precursor_da) = match precursor {
+            Some(ppm) => (Some(ppm), None),
+            None => (precursor_ppm, precursor_da),
+        };
+        let (fragment_ppm, fragment_da) = match fragment {
+            Some(ppm) => (Some(ppm), None),
+            None => (fragment_ppm, fragment_da),
+        };
+        let psms = self.score_all(
+            &file.spectra,
+            report_psms.unwrap_or(1),
+            precursor_ppm,
+            precursor_da,
+            fragment_ppm,
+            fragment_da,
+        )?;
+        Ok((psms, calibration))
+    }
+
+    /// search_dia(file, /, charges, candidates, cycles, min_fragments, fragment_ppm, fragment_da)
+    /// --
+    /// Spectrum-centric search of DIA data. Each MS2 scan is scored against
+    /// the peptides whose precursor m/z falls within its isolation window, at
+    /// each of `charges`. Candidates are grouped across consecutive cycles of
+    /// the same window, and reported with fragment XICs extracted from the
+    /// `cycles` scans either side of the best scoring (apex) scan
+    #[args(
+        charges = "vec![2, 3]",
+        candidates = "5",
+        cycles = "3",
+        min_fragments = "3"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn search_dia(
+        &self,
+        py: Python,
+        file: &spectra::Mzml,
+        charges: Option<Vec<u8>>,
+        candidates: Option<usize>,
+        cycles: Option<usize>,
+        min_fragments: Option<usize>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+    ) -> PyResult<Vec<dia::DiaMatch>> {
+        dia::search(
+            py,
+            self,
+            file,
+            &charges.unwrap_or_else(|| vec![2, 3]),
+            candidates.unwrap_or(5),
+            cycles.unwrap_or(3),
+            min_fragments.unwrap_or(3),
+            fragment_tolerance(fragment_ppm, fragment_da)?,
+        )
     }
 }
@@ -0,0 +1,938 @@
+use crate::annotate::annotate_peaks;
+use crate::lfq::{align_precursors, check_runs, confident_precursors};
+use crate::mass::{self, ProForma};
+use crate::psm::{stripped_sequence, Psm};
+use crate::spectra::{Mzml, Peak, Spectrum};
+use crate::stats::median;
+use crate::{fdr, fragment_tolerance, tolerance, unimod, Bounds, Database};
+use pyo3::exceptions::{PyFileNotFoundError, PyIOError, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use sage_core::mass::{Tolerance, PROTON};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct LibraryPeak {
+    #[pyo3(get)]
+    pub mz: f32,
+    #[pyo3(get)]
+    pub intensity: f32,
+    /// Fragment charge, 1 unless annotated otherwise
+    #[pyo3(get)]
+    pub charge: u8,
+    /// Fragment annotation, e.g. `y5^2`, if present in the library
+    #[pyo3(get)]
+    pub annotation: Option<String>,
+}
+
+impl LibraryPeak {
+    /// Singly charged mass, less a proton, as used for sage peaks
+    pub(crate) fn mass(&self) -> f32 {
+        (self.mz - PROTON) * self.charge as f32
+    }
+}
+
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct LibrarySpectrum {
+    /// Peptide sequence, including modifications e.g.: PEM[+15.9949]TIDEK
+    #[pyo3(get)]
+    pub peptide: String,
+    #[pyo3(get)]
+    pub charge: u8,
+    #[pyo3(get)]
+    pub precursor_mz: f32,
+    /// Retention time (or iRT), in the units used by the library
+    #[pyo3(get)]
+    pub rt: Option<f32>,
+    #[pyo3(get)]
+    pub proteins: Vec<String>,
+    #[pyo3(get)]
+    pub decoy: bool,
+    /// Fragment peaks, sorted by m/z
+    #[pyo3(get)]
+    pub peaks: Vec<LibraryPeak>,
+}
+
+#[pymethods]
+impl LibrarySpectrum {
+    fn __repr__(&self) -> String {
+        format!(
+            "{}/{} ({} peaks)",
+            self.peptide,
+            self.charge,
+            self.peaks.len()
+        )
+    }
+}
+
+/// Parse the charge from a fragment annotation such as `y5^2/0.01`
+fn annotation_charge(annotation: &str) -> u8 {
+    annotation
+        .split([',', '/', ' '])
+        .next()
+        .and_then(|ion| ion.split_once('^'))
+        .and_then(|(_, charge)| {
+            let digits = charge
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>();
+            digits.parse().ok()
+        })
+        .unwrap_or(1)
+}
+
+/// Split an MSP `Comment:` line into `key=value` pairs, respecting quotes
+fn comment_fields(comment: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in comment.chars().chain(std::iter::once(' ')) {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if let Some((key, value)) = token.split_once('=') {
+                    fields.insert(key.to_ascii_lowercase(), value.to_string());
+                }
+                token.clear();
+            }
+            c => token.push(c),
+        }
+    }
+    fields
+}
+
+/// Apply NIST-style `Mods=2(4,M,Oxidation)(0,A,Acetyl)` to a bare sequence
+fn apply_mods(sequence: &str, mods: &str) -> Result<String, String> {
+    let mut masses = vec![0.0f32; sequence.len()];
+    for spec in mods.split('(').skip(1) {
+        let spec = spec.trim_end_matches(')');
+        let mut parts = spec.splitn(3, ',');
+        let (position, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(position), Some(_), Some(name)) => (position, name),
+            _ => return Err(format!("invalid modification `{}`", spec)),
+        };
+        let position = position
+            .parse::<usize>()
+            .map_err(|_| format!("invalid modification `{}`", spec))?;
+        let modification =
+            unimod::lookup(name).ok_or_else(|| format!("unknown modification `{}`", name))?;
+        match masses.get_mut(position) {
+            Some(mass) => *mass += modification.monoisotopic,
+            None => return Err(format!("modification `{}` outside of sequence", spec)),
+        }
+    }
+    Ok(sequence
+        .chars()
+        .zip(masses)
+        .map(|(residue, mass)| match mass == 0.0 {
+            true => residue.to_string(),
+            false => format!("{}[{:+.4}]", residue, mass),
+        })
+        .collect())
+}
+
+#[derive(Default)]
+struct MspEntry {
+    name: String,
+    headers: HashMap<String, String>,
+    peaks: Vec<LibraryPeak>,
+}
+
+impl MspEntry {
+    fn finish(self) -> Result<LibrarySpectrum, String> {
+        let (sequence, charge) = match self.name.split_once('/') {
+            Some((sequence, charge)) => {
+                let charge = charge
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect::<String>();
+                (sequence.trim(), charge.parse::<u8>().ok())
+            }
+            None => (self.name.trim(), None),
+        };
+        let comment = comment_fields(self.headers.get("comment").map_or("", String::as_str));
+        let field = |key: &str| self.headers.get(key).or_else(|| comment.get(key));
+
+        let peptide = match comment.get("mods") {
+            Some(mods) if mods != "0" => apply_mods(sequence, mods)?,
+            _ => sequence.to_string(),
+        };
+        let charge = charge
+            .or_else(|| field("charge").and_then(|c| c.trim_end_matches('+').parse().ok()))
+            .ok_or_else(|| format!("no precursor charge for `{}`", self.name))?;
+
+        let precursor_mz = match field("precursormz")
+            .or_else(|| field("parent"))
+            .and_then(|mz| mz.parse::<f32>().ok())
+        {
+            Some(mz) => mz,
+            None => {
+                let peptide = ProForma::parse(&peptide)?;
+                mass::to_mz(peptide.monoisotopic(), charge)
+            }
+        };
+
+        let rt = ["retentiontime", "rt", "irt"]
+            .iter()
+            .find_map(|key| field(key).and_then(|rt| rt.split(',').next()?.parse().ok()));
+        let proteins = field("protein")
+            .map(|p| p.split(';').map(String::from).collect())
+            .unwrap_or_default();
+        let decoy = matches!(
+            field("decoy").map(String::as_str),
+            Some("1" | "true" | "True")
+        ) || field("remark")
+            .filter(|r| r.to_ascii_uppercase().contains("DECOY"))
+            .is_some();
+
+        let mut peaks = self.peaks;
+        peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+        Ok(LibrarySpectrum {
+            peptide,
+            charge,
+            precursor_mz,
+            rt,
+            proteins,
+            decoy,
+            peaks,
+        })
+    }
+}
+
+/// Parse an MSP (NIST) formatted spectral library
+pub fn parse_msp(contents: &str) -> Result<Vec<LibrarySpectrum>, String> {
+    let mut entries = Vec::new();
+    let mut current: Option<MspEntry> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("Name:") {
+            if let Some(entry) = current.take() {
+                entries.push(entry.finish()?);
+            }
+            current = Some(MspEntry {
+                name: name.trim().to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let entry = match current.as_mut() {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        let first = line.chars().next().unwrap_or_default();
+        if first.is_ascii_digit() {
+            let mut parts = line.split_whitespace();
+            let mz = parts.next().and_then(|mz| mz.parse::<f32>().ok());
+            let intensity = parts.next().and_then(|i| i.parse::<f32>().ok());
+            let annotation = Some(parts.collect::<Vec<_>>().join(" "))
+                .map(|a| a.trim_matches('"').to_string())
+                .filter(|a| !a.is_empty() && a != "?");
+            match (mz, intensity) {
+                (Some(mz), Some(intensity)) => entry.peaks.push(LibraryPeak {
+                    mz,
+                    intensity,
+                    charge: annotation.as_deref().map_or(1, annotation_charge),
+                    annotation,
+                }),
+                _ => return Err(format!("invalid peak `{}` in `{}`", line, entry.name)),
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            entry
+                .headers
+                .insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    if let Some(entry) = current.take() {
+        entries.push(entry.finish()?);
+    }
+    Ok(entries)
+}
+
+/// Does `path` point to an SQLite database (e.g. a .blib library)?
+pub(crate) fn is_sqlite(path: &str) -> PyResult<bool> {
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read(&mut header))
+        .map_err(|_| PyErr::new::<PyFileNotFoundError, _>(path.to_string()))?;
+    Ok(header.starts_with(b"SQLite format 3"))
+}
+
+/// Decode a BiblioSpec peak array, which is zlib-compressed unless doing so
+/// would not have saved space
+fn decode(blob: Vec<u8>, peaks: usize, width: usize) -> Result<Vec<u8>, String> {
+    if blob.len() == peaks * width {
+        return Ok(blob);
+    }
+    let mut out = Vec::with_capacity(peaks * width);
+    flate2::read::ZlibDecoder::new(&blob[..])
+        .read_to_end(&mut out)
+        .map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// Read a BiblioSpec (.blib) SQLite spectral library
+pub fn read_sqlite(path: &str) -> Result<Vec<LibrarySpectrum>, Box<dyn std::error::Error>> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    // Protein accessions are only present in newer BiblioSpec schemas
+    let mut proteins: HashMap<i64, Vec<String>> = HashMap::new();
+    if let Ok(mut stmt) = conn.prepare(
+        "SELECT rp.RefSpectraId, p.accession FROM RefSpectraProteins rp \
+         JOIN Proteins p ON rp.ProteinId = p.id",
+    ) {
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })? {
+            let (id, accession) = row?;
+            proteins.entry(id).or_default().push(accession);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT r.id, r.peptideModSeq, r.precursorCharge, r.precursorMZ, r.retentionTime, \
+         r.numPeaks, p.peakMZ, p.peakIntensity \
+         FROM RefSpectra r JOIN RefSpectraPeaks p ON r.id = p.RefSpectraID",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, f64>(3)?,
+            row.get::<_, Option<f64>>(4)?,
+            row.get::<_, i64>(5)?,
+            row.get::<_, Vec<u8>>(6)?,
+            row.get::<_, Vec<u8>>(7)?,
+        ))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        let (id, peptide, charge, precursor_mz, rt, n, mz, intensity) = row?;
+        let n = n as usize;
+        let mz = decode(mz, n, 8)?;
+        let intensity = decode(intensity, n, 4)?;
+        let mut peaks = mz
+            .chunks_exact(8)
+            .zip(intensity.chunks_exact(4))
+            .map(|(mz, intensity)| LibraryPeak {
+                mz: f64::from_le_bytes(mz.try_into().unwrap()) as f32,
+                intensity: f32::from_le_bytes(intensity.try_into().unwrap()),
+                charge: 1,
+                annotation: None,
+            })
+            .collect::<Vec<_>>();
+        peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+        entries.push(LibrarySpectrum {
+            peptide,
+            charge: charge as u8,
+            precursor_mz: precursor_mz as f32,
+            rt: rt.map(|rt| rt as f32),
+            proteins: proteins.remove(&id).unwrap_or_default(),
+            decoy: false,
+            peaks,
+        });
+    }
+    Ok(entries)
+}
+
+/// Library similarity scoring functions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Similarity {
+    /// Cosine similarity of square-root transformed intensities
+    Dot,
+    /// Entropy similarity (Li et al., Nature Methods 2021)
+    Entropy,
+}
+
+impl Similarity {
+    pub fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "dot" | "cosine" => Ok(Similarity::Dot),
+            "entropy" => Ok(Similarity::Entropy),
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unknown similarity `{}`: expected `dot` or `entropy`",
+                name
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Match {
+    score: f64,
+    matched_peaks: u32,
+    matched_intensity: f32,
+    ppm: f32,
+}
+
+fn entropy(intensities: impl Iterator<Item = f64>) -> f64 {
+    intensities.filter(|&p| p > 0.0).map(|p| -p * p.ln()).sum()
+}
+
+/// Reweight a probability distribution with low spectral entropy, as in the
+/// original entropy similarity description
+fn weighted(intensities: &[f64]) -> Vec<f64> {
+    let total = intensities.iter().sum::<f64>();
+    if total <= 0.0 {
+        return intensities.to_vec();
+    }
+    let p = intensities.iter().map(|i| i / total).collect::<Vec<_>>();
+    let s = entropy(p.iter().copied());
+    if s >= 3.0 {
+        return p;
+    }
+    let w = p
+        .iter()
+        .map(|x| x.powf(0.25 + s * 0.25))
+        .collect::<Vec<_>>();
+    let total = w.iter().sum::<f64>();
+    w.into_iter().map(|x| x / total).collect()
+}
+
+/// Score a query spectrum (peaks sorted by mass) against a library spectrum
+fn compare(query: &[Peak], library: &LibrarySpectrum, tol: Tolerance, method: Similarity) -> Match {
+    let mut result = Match::default();
+    // Index of the query peak matched by each library peak
+    let mut matches = vec![None; library.peaks.len()];
+    let mut used = vec![false; query.len()];
+    for (i, peak) in library.peaks.iter().enumerate() {
+        let mass = peak.mass();
+        let (lo, hi) = tol.bounds(mass);
+        let start = query.partition_point(|p| p.mass < lo);
+        let best = query[start..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, p)| p.mass <= hi)
+            .filter(|(j, _)| !used[start + j])
+            .max_by(|a, b| a.1.intensity.total_cmp(&b.1.intensity))
+            .map(|(j, _)| start + j);
+        if let Some(j) = best {
+            used[j] = true;
+            matches[i] = Some(j);
+            result.matched_peaks += 1;
+            result.matched_intensity += query[j].intensity;
+            result.ppm += ((query[j].mass - mass) / mass * 1E6).abs();
+        }
+    }
+    if result.matched_peaks > 0 {
+        result.ppm /= result.matched_peaks as f32;
+    }
+
+    let lib = library
+        .peaks
+        .iter()
+        .map(|p| p.intensity as f64)
+        .collect::<Vec<_>>();
+    let exp = query.iter().map(|p| p.intensity as f64).collect::<Vec<_>>();
+    result.score = match method {
+        Similarity::Dot => {
+            let norm = |v: &[f64]| v.iter().map(|x| x.max(0.0)).sum::<f64>().sqrt();
+            let (lib_norm, exp_norm) = (norm(&lib), norm(&exp));
+            let dot = matches
+                .iter()
+                .enumerate()
+                .filter_map(|(i, j)| j.map(|j| (lib[i] * exp[j]).max(0.0).sqrt()))
+                .sum::<f64>();
+            match lib_norm * exp_norm > 0.0 {
+                true => dot / (lib_norm * exp_norm),
+                false => 0.0,
+            }
+        }
+        Similarity::Entropy => {
+            let (lib, exp) = (weighted(&lib), weighted(&exp));
+            let mut merged = Vec::with_capacity(lib.len() + exp.len());
+            for (i, j) in matches.iter().enumerate() {
+                merged.push(lib[i] / 2.0 + j.map_or(0.0, |j| exp[j] / 2.0));
+            }
+            merged.extend((0..exp.len()).filter(|&j| !used[j]).map(|j| exp[j] / 2.0));
+            let s_ab = entropy(merged.into_iter());
+            let s_a = entropy(lib.into_iter());
+            let s_b = entropy(exp.into_iter());
+            (1.0 - (2.0 * s_ab - s_a - s_b) / 4f64.ln()).clamp(0.0, 1.0)
+        }
+    };
+    result
+}
+
+/// A confident identification used to build a library entry
+struct Replicate<'a> {
+    run: usize,
+    psm: &'a Psm,
+    /// Retention time, aligned onto the reference run
+    rt: f32,
+}
+
+/// Annotated fragment peaks of a PSM, with intensities relative to the base
+/// peak. Peaks matching several fragments carry all of their annotations
+fn annotated_peaks(
+    db: &Database,
+    run: &Mzml,
+    psm: &Psm,
+    tol: Tolerance,
+) -> PyResult<Vec<LibraryPeak>> {
+    let (query, peptide_ix) = match psm.peptide_ix {
+        Some(ix) => (run.get_sage_spectra(&psm.spectrum_title)?, ix),
+        None => return Ok(Vec::new()),
+    };
+    // Peaks are deconvoluted to charge 1, so only singly charged fragments
+    // are considered
+    let mut annotated = annotate_peaks(query, db.inner[peptide_ix].clone(), tol, Some(2));
+    annotated.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+
+    let mut peaks: Vec<LibraryPeak> = Vec::new();
+    for peak in annotated {
+        let label = format!("{}{}", peak.ion, peak.index);
+        match peaks.last_mut() {
+            Some(last) if last.mz == peak.mass + PROTON => {
+                if let Some(annotation) = last.annotation.as_mut() {
+                    annotation.push(',');
+                    annotation.push_str(&label);
+                }
+            }
+            _ => peaks.push(LibraryPeak {
+                mz: peak.mass + PROTON,
+                intensity: peak.intensity,
+                charge: 1,
+                annotation: Some(label),
+            }),
+        }
+    }
+    let base = peaks.iter().map(|p| p.intensity).fold(0.0, f32::max);
+    for peak in &mut peaks {
+        peak.intensity = match base > 0.0 {
+            true => 10000.0 * peak.intensity / base,
+            false => 0.0,
+        };
+    }
+    Ok(peaks)
+}
+
+/// Average replicate spectra, keeping fragments observed in at least half of
+/// the replicates
+fn merge_replicates(replicates: Vec<Vec<LibraryPeak>>) -> Vec<LibraryPeak> {
+    let n = replicates.len();
+    let mut fragments: HashMap<String, Vec<LibraryPeak>> = HashMap::new();
+    for peak in replicates.into_iter().flatten() {
+        if let Some(annotation) = peak.annotation.clone() {
+            fragments.entry(annotation).or_default().push(peak);
+        }
+    }
+    let mut peaks = fragments
+        .into_values()
+        .filter(|observed| observed.len() * 2 >= n)
+        .map(|observed| LibraryPeak {
+            mz: observed.iter().map(|p| p.mz).sum::<f32>() / observed.len() as f32,
+            intensity: observed.iter().map(|p| p.intensity).sum::<f32>() / n as f32,
+            charge: 1,
+            annotation: observed[0].annotation.clone(),
+        })
+        .collect::<Vec<_>>();
+    peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+    peaks
+}
+
+/// Format a peptide for BiblioSpec, which expects square brackets around
+/// modification masses
+fn blib_sequence(peptide: &str) -> String {
+    peptide.replace('(', "[").replace(')', "]")
+}
+
+#[pyclass]
+pub struct SpectralLibrary {
+    /// Library spectra, sorted by precursor m/z
+    pub spectra: Vec<LibrarySpectrum>,
+}
+
+impl SpectralLibrary {
+    pub fn from_spectra(mut spectra: Vec<LibrarySpectrum>) -> Self {
+        spectra.sort_by(|a, b| a.precursor_mz.total_cmp(&b.precursor_mz));
+        Self { spectra }
+    }
+
+    fn write_msp(&self, path: &str) -> std::io::Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for entry in &self.spectra {
+            writeln!(out, "Name: {}/{}", entry.peptide, entry.charge)?;
+            writeln!(out, "PrecursorMZ: {:.5}", entry.precursor_mz)?;
+            let mut comment = format!("Parent={:.5}", entry.precursor_mz);
+            if let Some(rt) = entry.rt {
+                comment.push_str(&format!(" RetentionTime={:.4}", rt));
+            }
+            if !entry.proteins.is_empty() {
+                comment.push_str(&format!(" Protein=\"{}\"", entry.proteins.join(";")));
+            }
+            if entry.decoy {
+                comment.push_str(" Decoy=1");
+            }
+            writeln!(out, "Comment: {}", comment)?;
+            writeln!(out, "Num peaks: {}", entry.peaks.len())?;
+            for peak in &entry.peaks {
+                match &peak.annotation {
+                    Some(annotation) => writeln!(
+                        out,
+                        "{:.5}\t{:.1}\t\"{}\"",
+                        peak.mz, peak.intensity, annotation
+                    )?,
+                    None => writeln!(out, "{:.5}\t{:.1}", peak.mz, peak.intensity)?,
+                }
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    fn write_sqlite(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_file(path)?;
+        }
+        let mut conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE LibInfo (libLSID TEXT, createTime TEXT, numSpecs INTEGER, \
+                majorVersion INTEGER, minorVersion INTEGER);
+             CREATE TABLE RefSpectra (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+                peptideSeq VARCHAR(150), precursorMZ REAL, precursorCharge INTEGER, \
+                peptideModSeq VARCHAR(200), prevAA CHAR(1), nextAA CHAR(1), copies INTEGER, \
+                numPeaks INTEGER, retentionTime REAL, fileID INTEGER, SpecIDinFile VARCHAR(256), \
+                score REAL, scoreType TINYINT);
+             CREATE TABLE RefSpectraPeaks (RefSpectraID INTEGER, peakMZ BLOB, peakIntensity BLOB);
+             CREATE TABLE Modifications (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+                RefSpectraID INTEGER, position INTEGER, mass REAL);
+             CREATE TABLE Proteins (id INTEGER PRIMARY KEY AUTOINCREMENT, accession VARCHAR(200));
+             CREATE TABLE RefSpectraProteins (RefSpectraId INTEGER, ProteinId INTEGER);",
+        )?;
+
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO LibInfo VALUES (?, datetime('now'), ?, 1, 10)",
+            rusqlite::params![
+                "urn:lsid:sage_proteomics:spectral_library:bibliospec:nr:library",
+                self.spectra.len() as i64
+            ],
+        )?;
+        let mut proteins: HashMap<&str, i64> = HashMap::new();
+        for entry in self.spectra.iter().filter(|entry| !entry.decoy) {
+            let peptide = ProForma::parse(&entry.peptide)?;
+            tx.execute(
+                "INSERT INTO RefSpectra (peptideSeq, precursorMZ, precursorCharge, peptideModSeq, \
+                    prevAA, nextAA, copies, numPeaks, retentionTime, fileID, SpecIDinFile, \
+                    score, scoreType) VALUES (?, ?, ?, ?, '-', '-', 1, ?, ?, 0, '', 0, 0)",
+                rusqlite::params![
+                    peptide.residues.iter().collect::<String>(),
+                    entry.precursor_mz as f64,
+                    entry.charge as i64,
+                    blib_sequence(&entry.peptide),
+                    entry.peaks.len() as i64,
+                    entry.rt.map(|rt| rt as f64)
+                ],
+            )?;
+            let id = tx.last_insert_rowid();
+
+            let mz = entry
+                .peaks
+                .iter()
+                .flat_map(|p| (p.mz as f64).to_le_bytes())
+                .collect::<Vec<u8>>();
+            let intensity = entry
+                .peaks
+                .iter()
+                .flat_map(|p| p.intensity.to_le_bytes())
+                .collect::<Vec<u8>>();
+            tx.execute(
+                "INSERT INTO RefSpectraPeaks VALUES (?, ?, ?)",
+                rusqlite::params![id, mz, intensity],
+            )?;
+
+            for (position, mass) in peptide.mods.iter().enumerate() {
+                let mass = match position {
+                    0 => mass + peptide.nterm,
+                    _ => *mass,
+                };
+                if mass != 0.0 {
+                    tx.execute(
+                        "INSERT INTO Modifications (RefSpectraID, position, mass) VALUES (?, ?, ?)",
+                        rusqlite::params![id, position as i64 + 1, mass as f64],
+                    )?;
+                }
+            }
+
+            for accession in &entry.proteins {
+                let protein = match proteins.get(accession.as_str()) {
+                    Some(protein) => *protein,
+                    None => {
+                        tx.execute(
+                            "INSERT INTO Proteins (accession) VALUES (?)",
+                            rusqlite::params![accession],
+                        )?;
+                        let protein = tx.last_insert_rowid();
+                        proteins.insert(accession, protein);
+                        protein
+                    }
+                };
+                tx.execute(
+                    "INSERT INTO RefSpectraProteins VALUES (?, ?)",
+                    rusqlite::params![id, protein],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl SpectralLibrary {
+    /// new(path)
+    /// --
+    /// Load a spectral library in MSP (NIST) format, or a BiblioSpec-style
+    /// SQLite library (.blib)
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        let spectra = match is_sqlite(path)? {
+            true => read_sqlite(path).map_err(|e| PyErr::new::<PyIOError, _>(e.to_string()))?,
+            false => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| PyErr::new::<PyIOError, _>(e.to_string()))?;
+                parse_msp(&contents).map_err(PyErr::new::<PyValueError, _>)?
+            }
+        };
+        Ok(Self::from_spectra(spectra))
+    }
+
+    /// from_psms(database, runs, psms, /, q_value_cutoff, fragment_ppm, consensus, max_replicates, min_peaks)
+    /// --
+    /// Build a library from confident PSMs, where `psms[i]` are the search
+    /// results of `runs[i]` against `database`. Each confident peptide and
+    /// charge state contributes its best scoring spectrum, or with `consensus`
+    /// the average of up to `max_replicates` spectra, annotated with matched
+    /// b- and y-ions. Retention times are aligned onto the run with the most
+    /// confident precursors
+    #[staticmethod]
+    #[args(
+        q_value_cutoff = "0.01",
+        fragment_ppm = "10.0",
+        consensus = "false",
+        max_replicates = "5",
+        min_peaks = "6"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn from_psms(
+        database: PyRef<Database>,
+        runs: Vec<PyRef<Mzml>>,
+        psms: Vec<Vec<PyRef<Psm>>>,
+        q_value_cutoff: Option<f32>,
+        fragment_ppm: Option<f32>,
+        consensus: Option<bool>,
+        max_replicates: Option<usize>,
+        min_peaks: Option<usize>,
+    ) -> PyResult<Self> {
+        check_runs(&runs, &psms)?;
+        let q_value_cutoff = q_value_cutoff.unwrap_or(0.01);
+        let fragment_ppm = fragment_ppm.unwrap_or(10.0);
+        let tol = Tolerance::Ppm(-fragment_ppm, fragment_ppm);
+        let max_replicates = match consensus.unwrap_or(false) {
+            true => max_replicates.unwrap_or(5).max(1),
+            false => 1,
+        };
+
+        let files = runs.iter().map(|run| run.file.clone()).collect::<Vec<_>>();
+        let alignments = align_precursors(
+            &files,
+            &psms
+                .iter()
+                .map(|psms| confident_precursors(psms, q_value_cutoff))
+                .collect::<Vec<_>>(),
+            10,
+        );
+
+        let mut precursors: HashMap<(&str, u8), Vec<Replicate>> = HashMap::new();
+        for (run, psms) in psms.iter().enumerate() {
+            let q_values = fdr::q_values(
+                &psms
+                    .iter()
+                    .map(|psm| (psm.hyperscore, psm.decoy))
+                    .collect::<Vec<_>>(),
+            );
+            for (psm, q) in psms.iter().zip(q_values) {
+                if psm.decoy || q > q_value_cutoff || psm.peptide_ix.is_none() {
+                    continue;
+                }
+                precursors
+                    .entry((psm.peptide.as_str(), psm.charge))
+                    .or_default()
+                    .push(Replicate {
+                        run,
+                        psm,
+                        rt: alignments[run].to_reference(psm.rt),
+                    });
+            }
+        }
+
+        let mut spectra = Vec::with_capacity(precursors.len());
+        for ((peptide, charge), mut replicates) in precursors {
+            replicates.sort_by(|a, b| b.psm.hyperscore.total_cmp(&a.psm.hyperscore));
+            replicates.truncate(max_replicates);
+
+            let mut annotated = Vec::with_capacity(replicates.len());
+            for replicate in &replicates {
+                annotated.push(annotated_peaks(
+                    &database,
+                    &runs[replicate.run],
+                    replicate.psm,
+                    tol,
+                )?);
+            }
+            let peaks = match annotated.len() {
+                1 => annotated.pop().unwrap_or_default(),
+                _ => merge_replicates(annotated),
+            };
+            if peaks.len() < min_peaks.unwrap_or(6) {
+                continue;
+            }
+
+            let best = replicates[0].psm;
+            spectra.push(LibrarySpectrum {
+                peptide: peptide.to_string(),
+                charge,
+                precursor_mz: mass::to_mz(best.calcmass, charge),
+                rt: median(&replicates.iter().map(|r| r.rt).collect::<Vec<_>>()),
+                proteins: best.proteins.clone(),
+                decoy: false,
+                peaks,
+            });
+        }
+        Ok(Self::from_spectra(spectra))
+    }
+
+    /// write(path, /, format)
+    /// --
+    /// Write the library to `path`, as MSP or a BiblioSpec-style SQLite
+    /// library (format "msp" or "blib"). The format defaults to "blib" for
+    /// paths ending in .blib, .sqlite or .db, and to "msp" otherwise. Existing
+    /// files are overwritten
+    fn write(&self, path: &str, format: Option<String>) -> PyResult<()> {
+        let format = format.unwrap_or_else(|| {
+            match path
+                .rsplit('.')
+                .next()
+                .map(str::to_ascii_lowercase)
+                .as_deref()
+            {
+                Some("blib" | "sqlite" | "db") => "blib".into(),
+                _ => "msp".into(),
+            }
+        });
+        match format.to_ascii_lowercase().as_str() {
+            "msp" => self
+                .write_msp(path)
+                .map_err(|e| PyErr::new::<PyIOError, _>(e.to_string())),
+            "blib" | "sqlite" => self
+                .write_sqlite(path)
+                .map_err(|e| PyErr::new::<PyIOError, _>(e.to_string())),
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unknown library format `{}`: expected `msp` or `blib`",
+                format
+            ))),
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.spectra.len()
+    }
+
+    /// get(index)
+    /// --
+    /// Return the library spectrum at `index`, in order of precursor m/z
+    fn get(&self, index: usize) -> PyResult<LibrarySpectrum> {
+        self.spectra.get(index).cloned().ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("library index {} out of range", index))
+        })
+    }
+
+    /// search(spectrum, /, report_psms, precursor_ppm, precursor_da, fragment_ppm, fragment_da, similarity)
+    /// --
+    /// Score library spectra within the precursor tolerance (and of the same
+    /// charge, if known) against a MS2 spectrum, returning the best
+    /// `report_psms` matches as PSMs. No hyperscore is computed: the
+    /// similarity is reported in the `library_similarity` feature
+    #[args(report_psms = 1, similarity = "\"dot\".into()")]
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        spectrum: Spectrum,
+        report_psms: Option<usize>,
+        precursor_ppm: Option<Bounds>,
+        precursor_da: Option<Bounds>,
+        fragment_ppm: Option<Bounds>,
+        fragment_da: Option<Bounds>,
+        similarity: Option<String>,
+    ) -> PyResult<Vec<Psm>> {
+        let method = Similarity::parse(similarity.as_deref().unwrap_or("dot"))?;
+        let precursor = spectrum.precursors.first().ok_or_else(|| {
+            PyErr::new::<PyValueError, _>(format!("Scan {} has no precursor", spectrum.title))
+        })?;
+        let precursor_tol = tolerance(precursor_ppm, precursor_da, Tolerance::Ppm(-20.0, 20.0))?;
+        let fragment_tol = fragment_tolerance(fragment_ppm, fragment_da)?;
+
+        let mut query = spectrum.peaks.clone();
+        query.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+        let total_intensity = query.iter().map(|p| p.intensity).sum::<f32>();
+
+        let (lo, hi) = precursor_tol.bounds(precursor.mz);
+        let start = self.spectra.partition_point(|s| s.precursor_mz < lo);
+        let end = self.spectra.partition_point(|s| s.precursor_mz <= hi);
+        let mut scored = self.spectra[start..end]
+            .par_iter()
+            .filter(|s| precursor.charge.filter(|&z| z != s.charge).is_none())
+            .map(|s| (s, compare(&query, s, fragment_tol, method)))
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.1.score.total_cmp(&a.1.score));
+
+        let candidates = scored.len() as u32;
+        Ok(scored
+            .iter()
+            .enumerate()
+            .take(report_psms.unwrap_or(1))
+            .map(|(rank, (entry, m))| {
+                let next = scored.get(rank + 1).map_or(0.0, |(_, n)| n.score);
+                let expmass = (precursor.mz - PROTON) * entry.charge as f32;
+                let calcmass = (entry.precursor_mz - PROTON) * entry.charge as f32;
+                Psm {
+                    peptide: entry.peptide.clone(),
+                    peptide_len: stripped_sequence(&entry.peptide).len(),
+                    proteins: entry.proteins.clone(),
+                    num_proteins: entry.proteins.len(),
+                    spectrum_title: spectrum.title.clone(),
+                    decoy: entry.decoy,
+                    expmass,
+                    calcmass,
+                    charge: entry.charge,
+                    rt: spectrum.scan_start_time,
+                    delta_mass: (expmass - calcmass) / calcmass * 1E6,
+                    average_ppm: m.ppm,
+                    matched_peaks: m.matched_peaks,
+                    matched_intensity_pct: match total_intensity > 0.0 {
+                        true => 100.0 * m.matched_intensity / total_intensity,
+                        false => 0.0,
+                    },
+                    scored_candidates: candidates,
+                    rank: rank as u32 + 1,
+                    residual_score: 1.0,
+                    features: HashMap::from([
+                        ("library_similarity".into(), m.score),
+                        ("library_delta_similarity".into(), m.score - next),
+                    ]),
+                    ..Default::default()
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,312 @@
+use crate::unimod;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use sage_core::mass::{Mass, H2O, NEUTRON, PROTON, VALID_AA};
+
+/// A peptide written in (a subset of) ProForma notation, e.g.
+/// `[Acetyl]-PEM[Oxidation]TIDEK[+8.0142]/2`. Modifications can be given
+/// as UNIMOD names, accessions (`UNIMOD:35`) or signed mass shifts, and
+/// sage-style parentheses (`M(+15.995)`) are accepted as well
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProForma {
+    pub residues: Vec<char>,
+    /// Summed modification mass on each residue
+    pub mods: Vec<f32>,
+    pub nterm: f32,
+    pub cterm: f32,
+    pub charge: Option<u8>,
+}
+
+/// Read a bracketed modification, after the opening `open` bracket, and
+/// return its mass
+fn bracket(chars: &mut std::str::Chars, open: char, proforma: &str) -> Result<f32, String> {
+    let close = if open == '[' { ']' } else { ')' };
+    let mut depth = 1;
+    let mut name = String::new();
+    for c in chars.by_ref() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            if c != close {
+                return Err(format!("mismatched brackets in `{}`", proforma));
+            }
+            return unimod::parse(&name).map(|(mass, _)| mass);
+        }
+        name.push(c);
+    }
+    Err(format!("unclosed modification in `{}`", proforma))
+}
+
+impl ProForma {
+    pub fn parse(proforma: &str) -> Result<Self, String> {
+        let (sequence, charge) = match proforma.rsplit_once('/') {
+            Some((sequence, charge)) => {
+                let charge = charge
+                    .trim()
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|&charge| charge > 0)
+                    .ok_or_else(|| format!("invalid charge state in `{}`", proforma))?;
+                (sequence, Some(charge))
+            }
+            None => (proforma, None),
+        };
+
+        let mut peptide = ProForma {
+            residues: Vec::new(),
+            mods: Vec::new(),
+            nterm: 0.0,
+            cterm: 0.0,
+            charge,
+        };
+        // Modifications seen since the last residue, not yet assigned
+        let mut pending = 0.0;
+        let mut chars = sequence.trim().chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '[' | '(' => {
+                    let mass = bracket(&mut chars, ch, proforma)?;
+                    match peptide.mods.last_mut() {
+                        Some(last) => *last += mass,
+                        None => pending += mass,
+                    }
+                }
+                '-' if peptide.residues.is_empty() => {
+                    peptide.nterm += pending;
+                    pending = 0.0;
+                }
+                '-' => {
+                    // Only C-terminal modifications may follow
+                    while let Some(ch) = chars.next() {
+                        match ch {
+                            '[' | '(' => peptide.cterm += bracket(&mut chars, ch, proforma)?,
+                            _ => {
+                                return Err(format!(
+                                    "unexpected `{}` after C-terminus in `{}`",
+                                    ch, proforma
+                                ))
+                            }
+                        }
+                    }
+                }
+                c if VALID_AA.contains(&c) => {
+                    peptide.residues.push(c);
+                    peptide.mods.push(0.0);
+                }
+                c => return Err(format!("invalid amino acid `{}` in `{}`", c, proforma)),
+            }
+        }
+
+        if pending != 0.0 {
+            return Err(format!(
+                "N-terminal modification must be followed by `-` in `{}`",
+                proforma
+            ));
+        }
+        if peptide.residues.is_empty() {
+            return Err(format!("no residues in `{}`", proforma));
+        }
+        Ok(peptide)
+    }
+
+    fn residue(&self, index: usize) -> f32 {
+        self.residues[index].monoisotopic() + self.mods[index]
+    }
+
+    /// Neutral monoisotopic mass
+    pub fn monoisotopic(&self) -> f32 {
+        (0..self.residues.len())
+            .map(|i| self.residue(i))
+            .sum::<f32>()
+            + self.nterm
+            + self.cterm
+            + H2O
+    }
+
+    /// Neutral masses of b-ions (`b1..b(n-1)`), followed by y-ions (`y1..y(n-1)`)
+    pub fn ladder(&self) -> (Vec<f32>, Vec<f32>) {
+        let n = self.residues.len();
+        let mut b = Vec::with_capacity(n.saturating_sub(1));
+        let mut mass = self.nterm;
+        for i in 0..n.saturating_sub(1) {
+            mass += self.residue(i);
+            b.push(mass);
+        }
+        let mut y = Vec::with_capacity(n.saturating_sub(1));
+        let mut mass = self.cterm + H2O;
+        for i in (1..n).rev() {
+            mass += self.residue(i);
+            y.push(mass);
+        }
+        (b, y)
+    }
+}
+
+fn parse(proforma: &str) -> PyResult<ProForma> {
+    ProForma::parse(proforma).map_err(PyErr::new::<PyValueError, _>)
+}
+
+/// Isotope distributions of the elements making up averagine, as
+/// (atoms per residue, relative abundance of +0, +1, +2... neutrons)
+const AVERAGINE: [(f64, &[f64]); 5] = [
+    (4.9384, &[0.9893, 0.0107]),
+    (7.7583, &[0.999885, 0.000115]),
+    (1.3577, &[0.99636, 0.00364]),
+    (1.4773, &[0.99757, 0.00038, 0.00205]),
+    (0.0417, &[0.9499, 0.0075, 0.0425, 0.0, 0.0001]),
+];
+const AVERAGINE_MASS: f64 = 111.1254;
+
+fn convolve(a: &[f64], b: &[f64], peaks: usize) -> Vec<f64> {
+    let mut out = vec![0.0; peaks.min((a.len() + b.len()).saturating_sub(1))];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            if let Some(o) = out.get_mut(i + j) {
+                *o += x * y;
+            }
+        }
+    }
+    out
+}
+
+/// Distribution of `atoms` atoms of an element, by repeated squaring
+fn element(dist: &[f64], mut atoms: u32, peaks: usize) -> Vec<f64> {
+    let mut out = vec![1.0];
+    let mut base = dist.to_vec();
+    while atoms > 0 {
+        if atoms & 1 == 1 {
+            out = convolve(&out, &base, peaks);
+        }
+        base = convolve(&base, &base, peaks);
+        atoms >>= 1;
+    }
+    out
+}
+
+/// Relative abundances of the first `peaks` isotopes of an averagine
+/// molecule of neutral mass `mass`, scaled so that the most abundant is 1
+pub fn averagine(mass: f32, peaks: usize) -> Vec<f32> {
+    if peaks == 0 {
+        return Vec::new();
+    }
+    let residues = mass.max(0.0) as f64 / AVERAGINE_MASS;
+    let mut envelope = vec![1.0];
+    for (per_residue, dist) in AVERAGINE {
+        let atoms = (per_residue * residues).round() as u32;
+        envelope = convolve(&envelope, &element(dist, atoms, peaks), peaks);
+    }
+    envelope.resize(peaks, 0.0);
+    let max = envelope.iter().cloned().fold(0.0, f64::max);
+    envelope
+        .into_iter()
+        .map(|x| if max > 0.0 { (x / max) as f32 } else { 0.0 })
+        .collect()
+}
+
+/// Convert a neutral mass to m/z at a non-zero `charge`
+pub(crate) fn to_mz(mass: f32, charge: u8) -> f32 {
+    (mass + charge as f32 * PROTON) / charge as f32
+}
+
+fn check_charge(charge: u8) -> PyResult<u8> {
+    match charge {
+        0 => Err(PyErr::new::<PyValueError, _>("charge must be at least 1")),
+        charge => Ok(charge),
+    }
+}
+
+/// mz(mass, charge)
+/// --
+/// Convert a neutral mass to m/z at `charge`
+#[pyfunction]
+pub fn mz(mass: f32, charge: u8) -> PyResult<f32> {
+    Ok(to_mz(mass, check_charge(charge)?))
+}
+
+/// ppm(a, b)
+/// --
+/// Mass error of `a` relative to `b`, in parts per million
+#[pyfunction]
+pub fn ppm(a: f32, b: f32) -> f32 {
+    (a - b) / b * 1E6
+}
+
+/// peptide_mass(proforma)
+/// --
+/// Neutral monoisotopic mass of a peptide given in ProForma notation
+#[pyfunction]
+pub fn peptide_mass(proforma: &str) -> PyResult<f32> {
+    Ok(parse(proforma)?.monoisotopic())
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct Fragment {
+    /// Ion type, 'b' or 'y'
+    #[pyo3(get)]
+    pub ion: char,
+    /// Number of residues in the fragment
+    #[pyo3(get)]
+    pub index: usize,
+    #[pyo3(get)]
+    pub charge: u8,
+    #[pyo3(get)]
+    pub mz: f32,
+}
+
+#[pymethods]
+impl Fragment {
+    fn __repr__(&self) -> String {
+        format!("{}{} {}+ {:.4}", self.ion, self.index, self.charge, self.mz)
+    }
+}
+
+/// fragment_ladder(proforma, /, max_charge)
+/// --
+/// b- and y-ion ladders of a peptide given in ProForma notation, at charge
+/// states 1 through `max_charge`
+#[pyfunction(max_charge = "1")]
+pub fn fragment_ladder(proforma: &str, max_charge: Option<u8>) -> PyResult<Vec<Fragment>> {
+    let max_charge = check_charge(max_charge.unwrap_or(1))?;
+    let (b, y) = parse(proforma)?.ladder();
+    let mut fragments = Vec::new();
+    for charge in 1..=max_charge {
+        for (ion, masses) in [('b', &b), ('y', &y)] {
+            fragments.extend(masses.iter().enumerate().map(|(idx, &mass)| Fragment {
+                ion,
+                index: idx + 1,
+                charge,
+                mz: to_mz(mass, charge),
+            }));
+        }
+    }
+    Ok(fragments)
+}
+
+/// isotope_envelope(mass, /, charge, peaks)
+/// --
+/// Averagine isotope envelope for a molecule of neutral monoisotopic `mass`,
+/// as `(position, relative abundance)` pairs. Positions are neutral masses,
+/// or m/z if `charge` is given
+#[pyfunction(peaks = "5")]
+pub fn isotope_envelope(
+    mass: f32,
+    charge: Option<u8>,
+    peaks: Option<usize>,
+) -> PyResult<Vec<(f32, f32)>> {
+    let charge = charge.map(check_charge).transpose()?;
+    Ok(averagine(mass, peaks.unwrap_or(5))
+        .into_iter()
+        .enumerate()
+        .map(|(isotope, abundance)| {
+            let mass = mass + isotope as f32 * NEUTRON;
+            match charge {
+                Some(charge) => (to_mz(mass, charge), abundance),
+                None => (mass, abundance),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,158 @@
+use crate::unimod;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use sage_core::mass::VALID_AA;
+use std::collections::HashMap;
+
+/// Modifications as accepted from Python: the plain `{residue: mass}` mapping,
+/// or specification strings such as `"Oxidation@M"`, `"UNIMOD:21@STY"`,
+/// `"Acetyl@N-term"` or `"+42.0106@K"`. Masses must be signed, so that
+/// `"35@M"` is not mistaken for `UNIMOD:35`
+#[derive(FromPyObject)]
+pub enum Mods {
+    Masses(HashMap<char, f32>),
+    Spec(String),
+    Specs(Vec<String>),
+}
+
+/// Peptide N-terminus, as understood by sage
+const NTERM: char = '^';
+
+fn error(msg: String) -> PyErr {
+    PyErr::new::<PyValueError, _>(msg)
+}
+
+/// Translate a site name into the site alphabet used by [`unimod::UNIMOD`]
+fn site(name: &str) -> PyResult<String> {
+    let site = match name.trim().to_ascii_lowercase().as_str() {
+        "n-term" | "nterm" => "^".into(),
+        "c-term" | "cterm" => "$".into(),
+        "protein n-term" => "[".into(),
+        "protein c-term" => "]".into(),
+        "any" | "*" => VALID_AA.iter().collect(),
+        _ => name.trim().to_string(),
+    };
+    if site.is_empty() {
+        return Err(error(format!("empty modification site in `{}`", name)));
+    }
+    Ok(site)
+}
+
+/// Check that a site can be searched by sage-core v0.7.1, which only knows
+/// residues and the peptide N-terminus
+fn position(site: char) -> Result<char, String> {
+    let unsupported = match site {
+        '[' => "protein N-terminal",
+        '$' => "peptide C-terminal",
+        ']' => "protein C-terminal",
+        c if c == NTERM || VALID_AA.contains(&c) => return Ok(c),
+        c => return Err(format!("invalid modification site `{}`", c)),
+    };
+    Err(format!(
+        "{} modifications cannot be searched with sage-core v0.7.1",
+        unsupported
+    ))
+}
+
+/// Parse a single specification string into `(site, mass)` pairs. Explicit
+/// sites that cannot be searched are an error, while the default UNIMOD
+/// sites of a modification are filtered down to the searchable ones
+fn parse(spec: &str) -> PyResult<Vec<(char, f32)>> {
+    let (name, sites) = match spec.split_once('@') {
+        Some((name, sites)) => (name.trim(), Some(site(sites)?)),
+        None => (spec.trim(), None),
+    };
+
+    let (mass, defaults) = unimod::parse(name).map_err(error)?;
+    let defaults = defaults.map(|m| m.sites);
+    match (sites, defaults) {
+        (Some(sites), _) => sites
+            .chars()
+            .map(|site| {
+                position(site)
+                    .map(|site| (site, mass))
+                    .map_err(|e| error(format!("`{}`: {}", spec, e)))
+            })
+            .collect(),
+        (None, Some(defaults)) => {
+            let sites = defaults
+                .chars()
+                .filter_map(|site| position(site).ok())
+                .map(|site| (site, mass))
+                .collect::<Vec<_>>();
+            if sites.is_empty() {
+                return Err(error(format!(
+                    "`{}`: none of its UNIMOD sites (`{}`) can be searched, \
+                     specify one with `{}@<site>`",
+                    spec, defaults, name
+                )));
+            }
+            Ok(sites)
+        }
+        (None, None) => Err(error(format!(
+            "modification `{}` needs a site, e.g. `{}@M`",
+            spec, name
+        ))),
+    }
+}
+
+impl Mods {
+    fn sites(self) -> PyResult<Vec<(char, f32)>> {
+        match self {
+            Mods::Masses(map) => map
+                .into_iter()
+                .map(|(site, mass)| position(site).map(|site| (site, mass)))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error),
+            Mods::Spec(spec) => parse(&spec),
+            Mods::Specs(specs) => {
+                let mut sites = Vec::new();
+                for spec in specs {
+                    sites.extend(parse(&spec)?);
+                }
+                Ok(sites)
+            }
+        }
+    }
+}
+
+/// Resolve static and variable modifications into the per-site mass tables
+/// used by sage. Static modifications on the same site are summed, while
+/// sage-core v0.7.1 can only search one variable modification per site
+#[allow(clippy::type_complexity)]
+pub fn resolve(
+    static_mods: Option<Mods>,
+    variable_mods: Option<Mods>,
+) -> PyResult<(Option<HashMap<char, f32>>, Option<HashMap<char, f32>>)> {
+    let static_mods = match static_mods {
+        Some(mods) => {
+            let mut map = HashMap::new();
+            for (site, mass) in mods.sites()? {
+                *map.entry(site).or_insert(0.0) += mass;
+            }
+            Some(map)
+        }
+        None => None,
+    };
+
+    let variable_mods = match variable_mods {
+        Some(mods) => {
+            let mut map = HashMap::new();
+            for (site, mass) in mods.sites()? {
+                if let Some(previous) = map.insert(site, mass) {
+                    if previous != mass {
+                        return Err(error(format!(
+                            "multiple variable modifications on `{}` ({:+} and {:+}): \
+                             sage-core v0.7.1 searches one variable modification per site",
+                            site, previous, mass
+                        )));
+                    }
+                }
+            }
+            Some(map)
+        }
+        None => None,
+    };
+
+    Ok((static_mods, variable_mods))
+}
@@ -0,0 +1,118 @@
+use crate::fdr;
+use crate::psm::Psm;
+use crate::stats::median;
+use crate::unimod;
+use pyo3::prelude::*;
+
+/// Precursor tolerance used for open searches, unless otherwise specified
+pub const OPEN_SEARCH_DA: (f32, f32) = (-150.0, 500.0);
+
+/// A cluster of PSMs sharing the same precursor mass shift
+#[pyclass]
+#[derive(Clone)]
+pub struct MassShift {
+    /// Median mass shift (experimental - calculated mass) of the cluster, in Da
+    #[pyo3(get)]
+    pub delta_mass: f32,
+    /// Lowest mass shift in the cluster
+    #[pyo3(get)]
+    pub min_delta_mass: f32,
+    /// Highest mass shift in the cluster
+    #[pyo3(get)]
+    pub max_delta_mass: f32,
+    /// Number of PSMs in the cluster
+    #[pyo3(get)]
+    pub psms: usize,
+    /// UNIMOD modifications matching the mass shift
+    #[pyo3(get)]
+    pub annotations: Vec<String>,
+}
+
+#[pymethods]
+impl MassShift {
+    fn __repr__(&self) -> String {
+        format!(
+            "{:+.4} Da [{} PSMs] {:?}",
+            self.delta_mass, self.psms, self.annotations
+        )
+    }
+}
+
+/// Cluster the mass shifts of confident PSMs: each cluster starts at the
+/// smallest unassigned mass shift and spans at most `bin_width` Da, so a
+/// dense run of shifts cannot chain into one wide cluster
+pub fn cluster(
+    psms: &[PyRef<Psm>],
+    q_value_cutoff: f32,
+    bin_width: f32,
+    min_psms: usize,
+    annotation_tolerance: f32,
+) -> Vec<MassShift> {
+    let q_values = fdr::q_values(
+        &psms
+            .iter()
+            .map(|psm| (psm.hyperscore, psm.decoy))
+            .collect::<Vec<_>>(),
+    );
+    let mut deltas = psms
+        .iter()
+        .zip(q_values)
+        .filter(|(psm, q)| !psm.decoy && *q <= q_value_cutoff)
+        .map(|(psm, _)| psm.expmass - psm.calcmass)
+        .collect::<Vec<_>>();
+    deltas.sort_unstable_by(|a, b| a.total_cmp(b));
+
+    let mut clusters: Vec<Vec<f32>> = Vec::new();
+    for delta in deltas {
+        match clusters.last_mut() {
+            Some(cluster) if delta - cluster[0] <= bin_width => cluster.push(delta),
+            _ => clusters.push(vec![delta]),
+        }
+    }
+
+    let mut shifts = clusters
+        .into_iter()
+        .filter(|cluster| cluster.len() >= min_psms.max(1))
+        .filter_map(|cluster| {
+            let delta_mass = median(&cluster)?;
+            Some(MassShift {
+                delta_mass,
+                min_delta_mass: cluster[0],
+                max_delta_mass: cluster[cluster.len() - 1],
+                psms: cluster.len(),
+                annotations: unimod::annotate(delta_mass, annotation_tolerance)
+                    .into_iter()
+                    .map(unimod::Modification::label)
+                    .collect(),
+            })
+        })
+        .collect::<Vec<_>>();
+    shifts.sort_by(|a, b| b.psms.cmp(&a.psms));
+    shifts
+}
+
+/// mass_shifts(psms, /, q_value_cutoff, bin_width, min_psms, annotation_tolerance)
+/// --
+/// Cluster the precursor mass shifts of confident PSMs from an open search
+/// into a mass shift histogram, annotated with matching UNIMOD modifications
+#[pyfunction(
+    q_value_cutoff = "0.01",
+    bin_width = "0.01",
+    min_psms = "5",
+    annotation_tolerance = "0.02"
+)]
+pub fn mass_shifts(
+    psms: Vec<PyRef<Psm>>,
+    q_value_cutoff: Option<f32>,
+    bin_width: Option<f32>,
+    min_psms: Option<usize>,
+    annotation_tolerance: Option<f32>,
+) -> Vec<MassShift> {
+    cluster(
+        &psms,
+        q_value_cutoff.unwrap_or(0.01),
+        bin_width.unwrap_or(0.01),
+        min_psms.unwrap_or(5),
+        annotation_tolerance.unwrap_or(0.02),
+    )
+}
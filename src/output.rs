@@ -0,0 +1,265 @@
+use crate::fdr;
+use crate::psm::Psm;
+use pyo3::exceptions::PyIOError;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+
+/// Columns of the sage command-line `results.sage.tsv`, in order. Reporter
+/// ion intensities are not part of it: sage writes them to a separate file
+const COLUMNS: &[&str] = &[
+    "peptide",
+    "proteins",
+    "num_proteins",
+    "filename",
+    "scannr",
+    "rank",
+    "label",
+    "expmass",
+    "calcmass",
+    "charge",
+    "peptide_len",
+    "missed_cleavages",
+    "isotope_error",
+    "precursor_ppm",
+    "fragment_ppm",
+    "hyperscore",
+    "delta_hyperscore",
+    "rt",
+    "predicted_rt",
+    "delta_rt",
+    "matched_peaks",
+    "longest_b",
+    "longest_y",
+    "longest_y_pct",
+    "matched_intensity_pct",
+    "scored_candidates",
+    "poisson",
+    "sage_discriminant_score",
+    "posterior_error",
+    "spectrum_q",
+    "peptide_q",
+    "protein_q",
+];
+
+/// Format a float the way sage does (shortest round-trip representation,
+/// always with a decimal point or exponent)
+fn float<T: std::fmt::Display>(x: T) -> String {
+    let s = x.to_string();
+    match s.contains(['.', 'e', 'E']) || s.contains("inf") || s.contains("NaN") {
+        true => s,
+        false => s + ".0",
+    }
+}
+
+/// Format an optional value, leaving the column empty when it is missing
+fn optional<T: std::fmt::Display>(x: Option<T>) -> String {
+    x.map(float).unwrap_or_default()
+}
+
+fn write<W: Write>(psms: &[&Psm], mut out: W, filename: &str) -> std::io::Result<()> {
+    let feature = |psm: &Psm, name: &str| psm.features.get(name).copied();
+
+    // Sage's linear discriminant is not available here, so PSMs are ranked by
+    // hyperscore unless a discriminant score was attached as a feature
+    let scores = psms
+        .iter()
+        .map(|psm| feature(psm, "sage_discriminant_score").unwrap_or(psm.hyperscore))
+        .collect::<Vec<_>>();
+
+    // As in sage, only rank-1 PSMs compete for spectrum-level q-values. Lower
+    // ranked and chimeric PSMs get the q-value of the score threshold at
+    // which they would be accepted: that of the lowest scoring rank-1 PSM
+    // still accepted at their score
+    let mut spectra = psms
+        .iter()
+        .zip(&scores)
+        .filter(|(psm, _)| psm.rank == 1)
+        .map(|(psm, &score)| (score, psm.decoy))
+        .collect::<Vec<_>>();
+    spectra.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+    let thresholds = spectra
+        .iter()
+        .map(|(score, _)| *score)
+        .zip(fdr::q_values(&spectra))
+        .collect::<Vec<_>>();
+    let spectrum_q = |score: f64| {
+        let idx = thresholds.partition_point(|(s, _)| *s < score);
+        match thresholds.get(idx).or(thresholds.last()) {
+            Some((_, q)) => *q,
+            None => 1.0,
+        }
+    };
+
+    // Peptide-level q-values, from the best scoring PSM of each peptide
+    let mut best: HashMap<(&str, bool), f64> = HashMap::new();
+    for (psm, &score) in psms.iter().zip(&scores) {
+        let entry = best
+            .entry((psm.peptide.as_str(), psm.decoy))
+            .or_insert(score);
+        *entry = entry.max(score);
+    }
+    let peptides = best.keys().copied().collect::<Vec<_>>();
+    let peptide_q = peptides
+        .iter()
+        .zip(fdr::q_values(
+            &peptides
+                .iter()
+                .map(|key| (best[key], key.1))
+                .collect::<Vec<_>>(),
+        ))
+        .map(|(key, q)| (*key, q))
+        .collect::<HashMap<_, _>>();
+
+    writeln!(out, "{}", COLUMNS.join("\t"))?;
+
+    for (psm, &score) in psms.iter().zip(&scores) {
+        let record = [
+            psm.peptide.clone(),
+            psm.proteins.join(";"),
+            psm.num_proteins.to_string(),
+            filename.to_string(),
+            psm.spectrum_title.clone(),
+            psm.rank.to_string(),
+            match psm.decoy {
+                true => "-1".into(),
+                false => "1".into(),
+            },
+            float(psm.expmass),
+            float(psm.calcmass),
+            psm.charge.to_string(),
+            psm.peptide_len.to_string(),
+            psm.missed_cleavages.to_string(),
+            float(psm.isotope_error),
+            float(psm.delta_mass),
+            float(psm.average_ppm),
+            float(psm.hyperscore),
+            float(psm.delta_hyperscore),
+            float(psm.rt),
+            optional(feature(psm, "predicted_rt").map(|x| x as f32)),
+            optional(feature(psm, "rt_error").map(|x| x as f32)),
+            psm.matched_peaks.to_string(),
+            psm.longest_b.to_string(),
+            psm.longest_y.to_string(),
+            float(psm.longest_y_pct),
+            float(psm.matched_intensity_pct),
+            psm.scored_candidates.to_string(),
+            float(psm.poisson),
+            optional(feature(psm, "sage_discriminant_score").map(|x| x as f32)),
+            optional(feature(psm, "posterior_error").map(|x| x as f32)),
+            float(spectrum_q(score)),
+            float(peptide_q[&(psm.peptide.as_str(), psm.decoy)]),
+            optional(feature(psm, "protein_q").map(|x| x as f32)),
+        ];
+        writeln!(out, "{}", record.join("\t"))?;
+    }
+    out.flush()
+}
+
+/// write_tsv(psms, path, /, filename)
+/// --
+/// Write PSMs with the column layout of the sage command-line
+/// `results.sage.tsv`. `filename` fills the `filename` column. Spectrum
+/// (rank-1 PSMs only) and peptide q-values are computed by target-decoy
+/// competition on the `sage_discriminant_score` feature, or the hyperscore.
+/// The `sage_discriminant_score`, `posterior_error`, `protein_q`,
+/// `predicted_rt` and `delta_rt` (`rt_error`) columns are taken from
+/// `Psm.features`, and left empty when missing. Reporter ion intensities are
+/// not written
+#[pyfunction]
+pub fn write_tsv(psms: Vec<PyRef<Psm>>, path: &str, filename: Option<String>) -> PyResult<()> {
+    let psms = psms.iter().map(|psm| &**psm).collect::<Vec<_>>();
+    std::fs::File::create(path)
+        .and_then(|file| {
+            write(
+                &psms,
+                BufWriter::new(file),
+                filename.as_deref().unwrap_or_default(),
+            )
+        })
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psm(title: &str, peptide: &str, rank: u32, decoy: bool, hyperscore: f64) -> Psm {
+        Psm {
+            peptide: peptide.into(),
+            peptide_len: peptide.len(),
+            proteins: vec!["sp|P1|A".into(), "sp|P2|B".into()],
+            num_proteins: 2,
+            spectrum_title: title.into(),
+            decoy,
+            expmass: 1000.5,
+            calcmass: 1000.0,
+            charge: 2,
+            rank,
+            hyperscore,
+            ..Default::default()
+        }
+    }
+
+    fn rows(psms: &[&Psm]) -> Vec<Vec<String>> {
+        let mut out = Vec::new();
+        write(psms, &mut out, "run.mzML").unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').map(String::from).collect())
+            .collect()
+    }
+
+    fn column(row: &[String], name: &str) -> String {
+        row[COLUMNS.iter().position(|c| *c == name).unwrap()].clone()
+    }
+
+    #[test]
+    fn writes_sage_rows() {
+        let mut target = psm("scan=1", "PEPTIDEK", 1, false, 10.0);
+        target.features.insert("posterior_error".into(), -2.5);
+        let decoy = psm("scan=2", "KEDITPEP", 1, true, 5.0);
+        let chimeric = psm("scan=1", "ELVISK", 2, false, 7.0);
+        let rows = rows(&[&target, &decoy, &chimeric]);
+
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], COLUMNS);
+        assert!(rows.iter().all(|row| row.len() == COLUMNS.len()));
+
+        let row = &rows[1];
+        assert_eq!(
+            row[..7],
+            [
+                "PEPTIDEK",
+                "sp|P1|A;sp|P2|B",
+                "2",
+                "run.mzML",
+                "scan=1",
+                "1",
+                "1"
+            ]
+        );
+        assert_eq!(column(row, "expmass"), "1000.5");
+        assert_eq!(column(row, "calcmass"), "1000.0");
+        assert_eq!(column(row, "hyperscore"), "10.0");
+        assert_eq!(column(&rows[2], "label"), "-1");
+
+        // Missing features leave their columns empty
+        for name in [
+            "predicted_rt",
+            "delta_rt",
+            "sage_discriminant_score",
+            "protein_q",
+        ] {
+            assert_eq!(column(row, name), "", "{}", name);
+        }
+        assert_eq!(column(row, "posterior_error"), "-2.5");
+
+        // Only rank-1 PSMs compete; the rank-2 PSM gets the q-value of the
+        // score threshold it passes
+        assert_eq!(column(&rows[1], "spectrum_q"), "0.0");
+        assert_eq!(column(&rows[2], "spectrum_q"), "1.0");
+        assert_eq!(column(&rows[3], "spectrum_q"), "0.0");
+    }
+}
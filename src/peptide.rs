@@ -0,0 +1,86 @@
+use crate::Database;
+use pyo3::prelude::*;
+use sage_core::database::IndexedDatabase;
+use sage_core::mass::Residue;
+
+/// A peptide from the digested and indexed database
+#[pyclass]
+#[derive(Clone)]
+pub struct Peptide {
+    /// Index of this peptide in the database
+    #[pyo3(get)]
+    pub index: usize,
+    /// Peptide sequence, including modifications e.g.: NC(+57.021)HK
+    #[pyo3(get)]
+    pub sequence: String,
+    /// Unmodified peptide sequence
+    #[pyo3(get)]
+    pub stripped_sequence: String,
+    /// Monoisotopic mass, including modifications
+    #[pyo3(get)]
+    pub monoisotopic: f32,
+    /// Is this a decoy peptide?
+    #[pyo3(get)]
+    pub decoy: bool,
+    /// Number of missed cleavages
+    #[pyo3(get)]
+    pub missed_cleavages: u8,
+    /// Proteins containing this peptide sequence
+    #[pyo3(get)]
+    pub proteins: Vec<String>,
+}
+
+#[pymethods]
+impl Peptide {
+    fn __repr__(&self) -> String {
+        format!("{} {:?}", self.sequence, self.proteins)
+    }
+}
+
+/// Unmodified sequence of a sage peptide
+pub fn stripped(peptide: &sage_core::peptide::Peptide) -> String {
+    peptide
+        .sequence
+        .iter()
+        .map(|residue| match residue {
+            Residue::Just(c) => *c,
+            Residue::Mod(c, _) => *c,
+        })
+        .collect()
+}
+
+impl Peptide {
+    pub fn new(db: &IndexedDatabase, index: usize) -> Option<Self> {
+        let peptide = db.peptides.get(index)?;
+        let (_, proteins) = db.assign_proteins(peptide);
+        Some(Self {
+            index,
+            sequence: peptide.to_string(),
+            stripped_sequence: stripped(peptide),
+            monoisotopic: peptide.monoisotopic,
+            decoy: peptide.decoy,
+            missed_cleavages: peptide.missed_cleavages,
+            proteins: proteins.split(';').map(Into::into).collect(),
+        })
+    }
+}
+
+/// Iterator over all peptides in a [`Database`]
+#[pyclass]
+pub struct PeptideIter {
+    pub db: Py<Database>,
+    pub index: usize,
+}
+
+#[pymethods]
+impl PeptideIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python) -> Option<Peptide> {
+        let index = slf.index;
+        slf.index += 1;
+        let db = slf.db.borrow(py);
+        Peptide::new(&db.inner, index)
+    }
+}
@@ -0,0 +1,347 @@
+use crate::fdr;
+use crate::library::{is_sqlite, parse_msp, read_sqlite, LibraryPeak};
+use crate::mass::ProForma;
+use crate::psm::Psm;
+use crate::spectra::Mzml;
+use crate::targeted::{header_columns, split_line};
+use crate::{fragment_tolerance, Bounds};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use sage_core::mass::Tolerance;
+use sage_core::spectrum::ProcessedSpectrum;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Column names accepted for predictions in CSV/TSV format. Covers Prosit's
+/// generic spectral library export and MS2PIP's CSV output (with a peptide
+/// column added), as well as RT-only predictions
+const COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "peptide",
+        &[
+            "modifiedpeptide",
+            "modifiedsequence",
+            "peptidemodifiedsequence",
+            "peptide",
+            "sequence",
+        ],
+    ),
+    ("charge", &["precursorcharge", "charge"]),
+    ("fragment_mz", &["fragmentmz", "productmz", "mz"]),
+    (
+        "intensity",
+        &["relativeintensity", "libraryintensity", "intensity"],
+    ),
+    // MS2PIP reports log2(intensity + 0.001)
+    ("log2_intensity", &["prediction"]),
+    ("fragment_type", &["fragmenttype", "iontype", "ion"]),
+    ("fragment_number", &["fragmentnumber", "ionnumber"]),
+    ("fragment_charge", &["fragmentcharge", "productcharge"]),
+    ("fragment_loss", &["fragmentlosstype", "losstype"]),
+    ("rt", &["irt", "predictedirt", "predictedrt", "rt", "tr"]),
+];
+
+/// Split a modified peptide into residues, each followed by its summed
+/// modification mass (rounded to 0.01 Da) if modified, e.g. `M[+15.99]`.
+/// Terminal modifications are folded into the terminal residues. Sequences
+/// written by sage, ProForma, Prosit (`_PEM[Oxidation (M)]K_`) and MSP
+/// libraries are all accepted
+pub fn residue_tokens(peptide: &str) -> Result<Vec<String>, String> {
+    // Drop Spectronaut/Prosit-style sites, e.g. `[Oxidation (M)]`
+    let mut cleaned = String::with_capacity(peptide.len());
+    let mut chars = peptide.trim().trim_matches('_').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' if chars.peek() == Some(&'(') => {
+                for c in chars.by_ref() {
+                    if c == ')' {
+                        break;
+                    }
+                }
+            }
+            c => cleaned.push(c),
+        }
+    }
+
+    let peptide = ProForma::parse(&cleaned)?;
+    Ok(peptide
+        .residues
+        .iter()
+        .zip(&peptide.mods)
+        .enumerate()
+        .map(|(idx, (residue, mass))| {
+            let mass = match idx {
+                0 => mass + peptide.nterm,
+                _ if idx + 1 == peptide.residues.len() => mass + peptide.cterm,
+                _ => *mass,
+            };
+            match (mass * 100.0).round() == 0.0 {
+                true => residue.to_string(),
+                false => format!("{}[{:+.2}]", residue, mass),
+            }
+        })
+        .collect())
+}
+
+/// Canonical form of a modified peptide, used to match sequences across
+/// notations: see [`residue_tokens`]
+pub fn peptide_key(peptide: &str) -> Result<String, String> {
+    residue_tokens(peptide).map(|tokens| tokens.concat())
+}
+
+/// Parse predictions in CSV/TSV format, one row per predicted fragment (or
+/// per peptide, for RT-only predictions)
+fn parse_csv(contents: &str) -> Result<Predictions, String> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().ok_or("empty prediction file")?;
+    let (delimiter, columns) = header_columns(header, COLUMNS);
+    if !columns.contains_key("peptide") {
+        return Err("prediction file has no peptide column".into());
+    }
+
+    let mut spectra: HashMap<(String, u8), Vec<LibraryPeak>> = HashMap::new();
+    let mut rt = HashMap::new();
+    for (line_number, line) in lines.enumerate() {
+        let fields = split_line(line, delimiter);
+        let get = |field: &str| {
+            columns
+                .get(field)
+                .and_then(|&idx| fields.get(idx))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        let number = |field: &str| -> Result<Option<f32>, String> {
+            get(field)
+                .map(|value| {
+                    value.parse::<f32>().map_err(|_| {
+                        format!("invalid {} `{}` on line {}", field, value, line_number + 2)
+                    })
+                })
+                .transpose()
+        };
+
+        let peptide = peptide_key(get("peptide").unwrap_or_default())
+            .map_err(|e| format!("{} on line {}", e, line_number + 2))?;
+        if let Some(irt) = number("rt")? {
+            rt.insert(peptide.clone(), irt);
+        }
+
+        let (charge, mz) = match (number("charge")?, number("fragment_mz")?) {
+            (Some(charge), Some(mz)) => (charge as u8, mz),
+            _ => continue,
+        };
+        let intensity = match (number("intensity")?, number("log2_intensity")?) {
+            (Some(intensity), _) => intensity,
+            (None, Some(log2)) => (2f32.powf(log2) - 0.001).max(0.0),
+            (None, None) => continue,
+        };
+        let fragment_charge = number("fragment_charge")?.map_or(1, |z| z as u8);
+        let annotation = get("fragment_type")
+            .zip(get("fragment_number"))
+            .map(|(ion, number)| {
+                let mut annotation = format!("{}{}", ion.to_ascii_lowercase(), number);
+                if let Some(loss) = get("fragment_loss").filter(|loss| *loss != "noloss") {
+                    annotation.push_str(&format!("-{}", loss));
+                }
+                if fragment_charge > 1 {
+                    annotation.push_str(&format!("^{}", fragment_charge));
+                }
+                annotation
+            });
+        spectra
+            .entry((peptide, charge))
+            .or_default()
+            .push(LibraryPeak {
+                mz,
+                intensity,
+                charge: fragment_charge,
+                annotation,
+            });
+    }
+    Ok(Predictions { spectra, rt })
+}
+
+/// Normalized spectral contrast angle between observed and predicted
+/// fragment intensities, over the predicted fragments: 1 is a perfect match,
+/// 0 an orthogonal spectrum
+fn spectral_angle(spectrum: &ProcessedSpectrum, predicted: &[LibraryPeak], tol: Tolerance) -> f64 {
+    let observed = predicted
+        .iter()
+        .map(|peak| {
+            let (lo, hi) = tol.bounds(peak.mass());
+            let start = spectrum.peaks.partition_point(|p| p.mass < lo);
+            spectrum.peaks[start..]
+                .iter()
+                .take_while(|p| p.mass <= hi)
+                .map(|p| p.intensity as f64)
+                .fold(0.0, f64::max)
+        })
+        .collect::<Vec<_>>();
+    let predicted = predicted
+        .iter()
+        .map(|p| p.intensity.max(0.0) as f64)
+        .collect::<Vec<_>>();
+
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let (a, b) = (norm(&observed), norm(&predicted));
+    if a == 0.0 || b == 0.0 {
+        return 0.0;
+    }
+    let dot = observed
+        .iter()
+        .zip(&predicted)
+        .map(|(x, y)| x * y)
+        .sum::<f64>()
+        / (a * b);
+    1.0 - 2.0 * dot.clamp(-1.0, 1.0).acos() / PI
+}
+
+/// Least squares fit of `y = intercept + slope * x`
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let var = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum::<f64>();
+    if points.len() < 2 || var <= 0.0 {
+        return None;
+    }
+    let cov = points
+        .iter()
+        .map(|p| (p.0 - mean_x) * (p.1 - mean_y))
+        .sum::<f64>();
+    let slope = cov / var;
+    Some((mean_y - slope * mean_x, slope))
+}
+
+/// Predicted fragment spectra and retention times (e.g. iRT), keyed by
+/// peptide and charge
+#[pyclass]
+pub struct Predictions {
+    spectra: HashMap<(String, u8), Vec<LibraryPeak>>,
+    rt: HashMap<String, f32>,
+}
+
+#[pymethods]
+impl Predictions {
+    /// new(path)
+    /// --
+    /// Load predicted spectra and/or retention times from a CSV/TSV file
+    /// (e.g. Prosit or MS2PIP output), an MSP library, or a .blib library
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        let library = match is_sqlite(path)? {
+            true => read_sqlite(path).map_err(|e| PyErr::new::<PyIOError, _>(e.to_string()))?,
+            false => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| PyErr::new::<PyIOError, _>(e.to_string()))?;
+                let msp = contents
+                    .lines()
+                    .find(|line| !line.trim().is_empty())
+                    .filter(|line| line.to_ascii_lowercase().starts_with("name:"))
+                    .is_some();
+                if !msp {
+                    return parse_csv(&contents).map_err(PyErr::new::<PyValueError, _>);
+                }
+                parse_msp(&contents).map_err(PyErr::new::<PyValueError, _>)?
+            }
+        };
+
+        let mut predictions = Self {
+            spectra: HashMap::new(),
+            rt: HashMap::new(),
+        };
+        for spectrum in library {
+            let key = peptide_key(&spectrum.peptide).map_err(PyErr::new::<PyValueError, _>)?;
+            if let Some(rt) = spectrum.rt {
+                predictions.rt.insert(key.clone(), rt);
+            }
+            predictions
+                .spectra
+                .insert((key, spectrum.charge), spectrum.peaks);
+        }
+        Ok(predictions)
+    }
+
+    fn __len__(&self) -> usize {
+        self.spectra.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Predictions({} spectra, {} retention times)",
+            self.spectra.len(),
+            self.rt.len()
+        )
+    }
+
+    /// attach(mzml, psms, /, q_value_cutoff, ppm, da)
+    /// --
+    /// Compute rescoring features for PSMs identified in `mzml`, stored in
+    /// `Psm.features`:
+    /// - `spectral_angle`: normalized spectral contrast angle between the
+    ///   PSM's spectrum and the predicted spectrum of its peptide and charge
+    /// - `predicted_irt`: the predicted retention time, as given
+    /// - `calibrated_rt`: the prediction mapped onto the run's RT scale, by
+    ///   a linear fit against confident target PSMs (q <= `q_value_cutoff`)
+    /// - `delta_rt`: |observed RT - calibrated RT|
+    ///
+    /// Returns the (intercept, slope) of the RT calibration, if one could be
+    /// fitted
+    #[args(q_value_cutoff = "0.01")]
+    fn attach(
+        &self,
+        mzml: &Mzml,
+        mut psms: Vec<PyRefMut<Psm>>,
+        q_value_cutoff: Option<f32>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+    ) -> PyResult<Option<(f64, f64)>> {
+        let tolerance = fragment_tolerance(ppm, da)?;
+        let keys = psms
+            .iter()
+            .map(|psm| peptide_key(&psm.peptide).ok())
+            .collect::<Vec<_>>();
+
+        let q_values = fdr::q_values(
+            &psms
+                .iter()
+                .map(|psm| (psm.hyperscore, psm.decoy))
+                .collect::<Vec<_>>(),
+        );
+        let cutoff = q_value_cutoff.unwrap_or(0.01);
+        let points = psms
+            .iter()
+            .zip(&keys)
+            .zip(&q_values)
+            .filter(|((psm, _), &q)| !psm.decoy && q <= cutoff)
+            .filter_map(|((psm, key), _)| {
+                let irt = self.rt.get(key.as_ref()?)?;
+                Some((*irt as f64, psm.rt as f64))
+            })
+            .collect::<Vec<_>>();
+        let calibration = linear_fit(&points);
+
+        for (psm, key) in psms.iter_mut().zip(&keys) {
+            let key = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            if let Some(predicted) = self.spectra.get(&(key.clone(), psm.charge)) {
+                if let Ok(spectrum) = mzml.get_sage_spectra(&psm.spectrum_title) {
+                    let angle = spectral_angle(spectrum, predicted, tolerance);
+                    psm.features.insert("spectral_angle".into(), angle);
+                }
+            }
+            if let Some(&irt) = self.rt.get(key) {
+                psm.features.insert("predicted_irt".into(), irt as f64);
+                if let Some((intercept, slope)) = calibration {
+                    let rt = intercept + slope * irt as f64;
+                    psm.features.insert("calibrated_rt".into(), rt);
+                    psm.features
+                        .insert("delta_rt".into(), (psm.rt as f64 - rt).abs());
+                }
+            }
+        }
+        Ok(calibration)
+    }
+}
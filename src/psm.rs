@@ -1,10 +1,14 @@
 use pyo3::prelude::*;
 use sage_core::database::PeptideIx;
+use sage_core::mass::PROTON;
 use sage_core::scoring::Feature;
+use std::collections::HashMap;
 
 #[pyclass]
+#[derive(Default)]
 pub struct Psm {
-    pub peptide_ix: PeptideIx,
+    /// Index of the peptide in the `Database`, if matched against one
+    pub peptide_ix: Option<PeptideIx>,
     /// Peptide sequence, including modifications e.g.: NC(+57.021)HK
     #[pyo3(get)]
     pub peptide: String,
@@ -73,6 +77,21 @@ pub struct Psm {
     /// Probability of matching exactly N peaks across all candidates Pr(x=k)
     #[pyo3(get)]
     pub poisson: f64,
+    /// Isobaric reporter ion intensities, if quantified
+    #[pyo3(get, set)]
+    pub reporter_ions: Option<Vec<f32>>,
+    /// Rank of this PSM among the PSMs reported for the spectrum (1 is best)
+    #[pyo3(get)]
+    pub rank: u32,
+    /// Fraction of the spectrum's intensity left when this PSM was scored,
+    /// after the fragment peaks matched by higher-ranked chimeric PSMs were
+    /// subtracted. Always 1.0 outside of chimeric search
+    #[pyo3(get)]
+    pub residual_score: f64,
+    /// Additional rescoring features, e.g. from predicted spectra and
+    /// retention times, keyed by name
+    #[pyo3(get, set)]
+    pub features: HashMap<String, f64>,
 }
 
 #[pymethods]
@@ -82,10 +101,37 @@ impl Psm {
     }
 }
 
+/// Remove modifications from a peptide sequence, e.g. NC(+57.021)HK -> NCHK
+pub fn stripped_sequence(peptide: &str) -> String {
+    let mut depth = 0usize;
+    peptide
+        .chars()
+        .filter(|&ch| match ch {
+            '(' | '[' => {
+                depth += 1;
+                false
+            }
+            ')' | ']' => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            _ => depth == 0 && ch.is_ascii_uppercase(),
+        })
+        .collect()
+}
+
+impl Psm {
+    /// Position of the monoisotopic precursor peak at `charge`, in the units
+    /// used for MS1 peaks
+    pub fn precursor_mass(&self, charge: u8) -> f32 {
+        (self.expmass - self.isotope_error - PROTON) / charge as f32
+    }
+}
+
 impl From<Feature> for Psm {
     fn from(p: Feature) -> Self {
         Self {
-            peptide_ix: p.peptide_idx,
+            peptide_ix: Some(p.peptide_idx),
             peptide: p.peptide,
             peptide_len: p.peptide_len,
             proteins: p.proteins.split(";").map(Into::into).collect(),
@@ -109,6 +155,10 @@ impl From<Feature> for Psm {
             matched_intensity_pct: p.matched_intensity_pct,
             scored_candidates: p.scored_candidates,
             poisson: p.poisson,
+            reporter_ions: None,
+            rank: 1,
+            residual_score: 1.0,
+            features: HashMap::new(),
         }
     }
 }
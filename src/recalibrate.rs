@@ -0,0 +1,297 @@
+use crate::annotate::annotate_peaks;
+use crate::psm::Psm;
+use crate::spectra::Mzml;
+use crate::stats::{median, solve};
+use crate::{fdr, Bounds, Database};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use sage_core::mass::{Tolerance, PROTON};
+use sage_core::spectrum::ProcessedSpectrum;
+
+/// Below this many observations, only a constant offset is fitted
+const MIN_POINTS: usize = 50;
+
+/// Recalibrated tolerances span this many robust standard deviations of the
+/// remaining mass error
+const SIGMAS: f64 = 4.0;
+
+/// A mass error observation: retention time, m/z and signed ppm error
+type Observation = (f32, f32, f64);
+
+/// Systematic mass error (ppm) as a quadratic function of retention time and
+/// m/z, both scaled to [0, 1] over the observed range
+#[derive(Clone, Debug)]
+pub struct OffsetModel {
+    coefficients: Vec<f64>,
+    rt: (f32, f32),
+    mz: (f32, f32),
+    /// Robust standard deviation (1.4826 * MAD) of the residual error
+    sigma: f64,
+    points: usize,
+}
+
+fn scale(x: f32, (lo, hi): (f32, f32)) -> f64 {
+    match hi > lo {
+        true => ((x.clamp(lo, hi) - lo) / (hi - lo)) as f64,
+        false => 0.0,
+    }
+}
+
+fn robust_sigma(residuals: &[f32]) -> f64 {
+    let center = median(residuals).unwrap_or_default();
+    let deviations = residuals
+        .iter()
+        .map(|r| (r - center).abs())
+        .collect::<Vec<_>>();
+    1.4826 * median(&deviations).unwrap_or_default() as f64
+}
+
+impl OffsetModel {
+    fn basis(&self, rt: f32, mz: f32) -> [f64; 5] {
+        let (r, m) = (scale(rt, self.rt), scale(mz, self.mz));
+        [1.0, r, r * r, m, m * m]
+    }
+
+    /// Systematic mass error, in ppm, at a given retention time and m/z
+    pub fn offset(&self, rt: f32, mz: f32) -> f64 {
+        self.basis(rt, mz)
+            .iter()
+            .zip(&self.coefficients)
+            .map(|(x, c)| x * c)
+            .sum()
+    }
+
+    fn residuals(&self, points: &[Observation]) -> Vec<f32> {
+        points
+            .iter()
+            .map(|&(rt, mz, ppm)| (ppm - self.offset(rt, mz)) as f32)
+            .collect()
+    }
+
+    fn least_squares(&mut self, points: &[Observation]) -> Option<()> {
+        let n = self.coefficients.len();
+        let mut a = vec![vec![0.0; n]; n];
+        let mut b = vec![0.0; n];
+        for &(rt, mz, ppm) in points {
+            let x = self.basis(rt, mz);
+            for i in 0..n {
+                b[i] += x[i] * ppm;
+                for j in 0..n {
+                    a[i][j] += x[i] * x[j];
+                }
+            }
+        }
+        // Slight ridge penalty, in case RT or m/z barely vary
+        for (i, row) in a.iter_mut().enumerate().skip(1) {
+            row[i] += 1E-6 * points.len() as f64;
+        }
+        self.coefficients = solve(a, b)?;
+        Some(())
+    }
+
+    /// Fit the model, refitting once after discarding observations more than
+    /// 3 robust standard deviations from the first fit
+    fn fit(points: &[Observation]) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+        let range = |values: &mut dyn Iterator<Item = f32>| {
+            values.fold((f32::MAX, f32::MIN), |(lo, hi), x| (lo.min(x), hi.max(x)))
+        };
+        let mut model = OffsetModel {
+            coefficients: vec![0.0; 5],
+            rt: range(&mut points.iter().map(|p| p.0)),
+            mz: range(&mut points.iter().map(|p| p.1)),
+            sigma: 0.0,
+            points: points.len(),
+        };
+
+        if points.len() < MIN_POINTS {
+            let errors = points.iter().map(|p| p.2 as f32).collect::<Vec<_>>();
+            model.coefficients[0] = median(&errors).unwrap_or_default() as f64;
+        } else {
+            model.least_squares(points)?;
+            let sigma = robust_sigma(&model.residuals(points));
+            let inliers = points
+                .iter()
+                .zip(model.residuals(points))
+                .filter(|(_, r)| sigma == 0.0 || (*r as f64).abs() <= 3.0 * sigma)
+                .map(|(p, _)| *p)
+                .collect::<Vec<_>>();
+            if inliers.len() >= MIN_POINTS {
+                model.least_squares(&inliers)?;
+            }
+        }
+        model.sigma = robust_sigma(&model.residuals(points));
+        Some(model)
+    }
+
+    /// Symmetric ppm tolerance covering the residual error, at least 1 ppm
+    fn tolerance(&self) -> (f32, f32) {
+        let width = (SIGMAS * self.sigma).max(1.0) as f32;
+        (-width, width)
+    }
+}
+
+/// Correct a mass or m/z for a systematic error of `ppm`
+fn correct(mass: f32, ppm: f64) -> f32 {
+    (mass as f64 / (1.0 + ppm * 1E-6)) as f32
+}
+
+/// Precursor and fragment mass calibration, fitted on confident PSMs
+#[pyclass]
+#[derive(Clone)]
+pub struct MassCalibration {
+    precursor: Option<OffsetModel>,
+    fragment: Option<OffsetModel>,
+}
+
+impl MassCalibration {
+    /// Fit precursor and fragment mass errors on the confident target PSMs
+    /// of `file`. Fragment errors are taken from the b- and y-ions matched
+    /// within `fragment` tolerance
+    pub fn fit(
+        db: &Database,
+        file: &Mzml,
+        psms: &[&Psm],
+        q_value_cutoff: f32,
+        fragment: Tolerance,
+    ) -> Self {
+        let q_values = fdr::q_values(
+            &psms
+                .iter()
+                .map(|psm| (psm.hyperscore, psm.decoy))
+                .collect::<Vec<_>>(),
+        );
+        let confident = psms
+            .iter()
+            .zip(q_values)
+            .filter(|(psm, q)| !psm.decoy && *q <= q_value_cutoff)
+            .filter_map(|(psm, _)| {
+                let spectrum = file.get_sage_spectra(&psm.spectrum_title).ok()?;
+                Some((*psm, spectrum))
+            })
+            .collect::<Vec<_>>();
+
+        let precursors = confident
+            .iter()
+            .filter_map(|(psm, spectrum)| {
+                let mz = spectrum.precursors.first()?.mz;
+                let ppm = (psm.expmass - psm.isotope_error - psm.calcmass) / psm.calcmass * 1E6;
+                Some((spectrum.scan_start_time, mz, ppm as f64))
+            })
+            .collect::<Vec<_>>();
+
+        let fragments = confident
+            .par_iter()
+            .filter_map(|(psm, spectrum)| Some((psm.peptide_ix?, spectrum)))
+            .flat_map_iter(|(ix, spectrum)| {
+                annotate_peaks(spectrum, db.inner[ix].clone(), fragment, Some(2))
+                    .into_iter()
+                    .map(move |peak| {
+                        (
+                            spectrum.scan_start_time,
+                            peak.mass + PROTON,
+                            peak.ppm_error as f64,
+                        )
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        MassCalibration {
+            precursor: OffsetModel::fit(&precursors),
+            fragment: OffsetModel::fit(&fragments),
+        }
+    }
+
+    /// Correct precursor m/z and MS1 peaks with the precursor model, and MSn
+    /// peaks with the fragment model
+    pub fn correct(&self, spectrum: &mut ProcessedSpectrum) {
+        let rt = spectrum.scan_start_time;
+        if let Some(model) = &self.precursor {
+            for precursor in spectrum.precursors.iter_mut() {
+                precursor.mz = correct(precursor.mz, model.offset(rt, precursor.mz));
+            }
+        }
+        let model = match spectrum.level {
+            1 => &self.precursor,
+            _ => &self.fragment,
+        };
+        if let Some(model) = model {
+            for peak in spectrum.peaks.iter_mut() {
+                peak.mass = correct(peak.mass, model.offset(rt, peak.mass + PROTON));
+            }
+            spectrum.peaks.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+        }
+    }
+
+    /// Data-derived (precursor, fragment) ppm tolerances for a re-search
+    pub fn tolerances(&self) -> (Option<Bounds>, Option<Bounds>) {
+        let bounds = |model: &Option<OffsetModel>| {
+            model.as_ref().map(|m| {
+                let (lo, hi) = m.tolerance();
+                Bounds::Asymmetric(lo, hi)
+            })
+        };
+        (bounds(&self.precursor), bounds(&self.fragment))
+    }
+}
+
+#[pymethods]
+impl MassCalibration {
+    /// Number of PSMs used to fit the precursor mass error
+    #[getter]
+    fn precursor_points(&self) -> usize {
+        self.precursor.as_ref().map_or(0, |m| m.points)
+    }
+
+    /// Number of matched fragment ions used to fit the fragment mass error
+    #[getter]
+    fn fragment_points(&self) -> usize {
+        self.fragment.as_ref().map_or(0, |m| m.points)
+    }
+
+    /// Recommended precursor tolerance (ppm) after recalibration
+    #[getter]
+    fn precursor_tolerance(&self) -> Option<(f32, f32)> {
+        self.precursor.as_ref().map(OffsetModel::tolerance)
+    }
+
+    /// Recommended fragment tolerance (ppm) after recalibration
+    #[getter]
+    fn fragment_tolerance(&self) -> Option<(f32, f32)> {
+        self.fragment.as_ref().map(OffsetModel::tolerance)
+    }
+
+    /// precursor_offset(rt, mz)
+    /// --
+    /// Systematic precursor mass error (ppm) at a retention time and m/z
+    fn precursor_offset(&self, rt: f32, mz: f32) -> Option<f64> {
+        self.precursor.as_ref().map(|m| m.offset(rt, mz))
+    }
+
+    /// fragment_offset(rt, mz)
+    /// --
+    /// Systematic fragment mass error (ppm) at a retention time and m/z
+    fn fragment_offset(&self, rt: f32, mz: f32) -> Option<f64> {
+        self.fragment.as_ref().map(|m| m.offset(rt, mz))
+    }
+
+    /// apply(mzml)
+    /// --
+    /// Recalibrate the spectra of `mzml` in place. Calibrations are not
+    /// idempotent: apply each one only once
+    fn apply(&self, mut mzml: PyRefMut<Mzml>) {
+        mzml.spectra
+            .par_iter_mut()
+            .for_each(|spectrum| self.correct(spectrum));
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MassCalibration(precursor_points={}, fragment_points={})",
+            self.precursor_points(),
+            self.fragment_points()
+        )
+    }
+}
@@ -0,0 +1,230 @@
+use crate::fdr;
+use crate::prediction::residue_tokens;
+use crate::psm::Psm;
+use crate::stats::solve;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// Minimum number of confident peptides required to train a model
+const MIN_PEPTIDES: usize = 10;
+
+/// Number of cross-fitting folds used to predict the RT of training peptides
+const FOLDS: usize = 5;
+
+/// Linear retention time model on amino acid composition: the predicted RT
+/// is `intercept + length * ln(len) + sum(coefficients[residue])`, summed
+/// over the residues of the peptide. Modified residues (e.g. `M[+15.99]`)
+/// have their own coefficients
+#[pyclass]
+#[derive(Clone)]
+pub struct RtModel {
+    #[pyo3(get)]
+    pub intercept: f64,
+    /// Coefficient of the natural logarithm of peptide length
+    #[pyo3(get)]
+    pub length: f64,
+    /// Retention coefficient of each (modified) residue
+    #[pyo3(get)]
+    pub coefficients: HashMap<String, f64>,
+    /// Number of peptides the model was trained on
+    #[pyo3(get)]
+    pub peptides: usize,
+    /// Coefficient of determination on the training peptides
+    #[pyo3(get)]
+    pub r_squared: f64,
+}
+
+impl RtModel {
+    fn predict_tokens(&self, tokens: &[String]) -> f64 {
+        self.intercept
+            + self.length * (tokens.len() as f64).ln()
+            + tokens
+                .iter()
+                .filter_map(|token| self.coefficients.get(token))
+                .sum::<f64>()
+    }
+
+    /// Ridge regression of `rt` on residue composition and log length.
+    /// Features and RT are centered, so the intercept is not penalized
+    fn fit(peptides: &[(Vec<String>, f64)], alpha: f64) -> Option<Self> {
+        let mut vocabulary = peptides
+            .iter()
+            .flat_map(|(tokens, _)| tokens.iter().cloned())
+            .collect::<Vec<_>>();
+        vocabulary.sort();
+        vocabulary.dedup();
+        let index = vocabulary
+            .iter()
+            .enumerate()
+            .map(|(idx, token)| (token.as_str(), idx))
+            .collect::<HashMap<_, _>>();
+
+        // Feature 0 is ln(length), followed by residue counts
+        let n = vocabulary.len() + 1;
+        let rows = peptides
+            .iter()
+            .map(|(tokens, _)| {
+                let mut row = vec![0.0; n];
+                row[0] = (tokens.len() as f64).ln();
+                for token in tokens {
+                    row[index[token.as_str()] + 1] += 1.0;
+                }
+                row
+            })
+            .collect::<Vec<_>>();
+        let m = peptides.len() as f64;
+        let means = (0..n)
+            .map(|j| rows.iter().map(|row| row[j]).sum::<f64>() / m)
+            .collect::<Vec<_>>();
+        let mean_rt = peptides.iter().map(|(_, rt)| rt).sum::<f64>() / m;
+
+        let mut a = vec![vec![0.0; n]; n];
+        let mut b = vec![0.0; n];
+        for (row, (_, rt)) in rows.iter().zip(peptides) {
+            let x = row
+                .iter()
+                .zip(&means)
+                .map(|(x, mean)| x - mean)
+                .collect::<Vec<_>>();
+            for i in 0..n {
+                b[i] += x[i] * (rt - mean_rt);
+                for j in 0..n {
+                    a[i][j] += x[i] * x[j];
+                }
+            }
+        }
+        for (i, row) in a.iter_mut().enumerate() {
+            row[i] += alpha;
+        }
+        let beta = solve(a, b)?;
+
+        let mut model = RtModel {
+            intercept: mean_rt - beta.iter().zip(&means).map(|(b, m)| b * m).sum::<f64>(),
+            length: beta[0],
+            coefficients: vocabulary
+                .into_iter()
+                .zip(beta[1..].iter().copied())
+                .collect(),
+            peptides: peptides.len(),
+            r_squared: 0.0,
+        };
+        let ss_res = peptides
+            .iter()
+            .map(|(tokens, rt)| (rt - model.predict_tokens(tokens)).powi(2))
+            .sum::<f64>();
+        let ss_tot = peptides
+            .iter()
+            .map(|(_, rt)| (rt - mean_rt).powi(2))
+            .sum::<f64>();
+        if ss_tot > 0.0 {
+            model.r_squared = 1.0 - ss_res / ss_tot;
+        }
+        Some(model)
+    }
+}
+
+#[pymethods]
+impl RtModel {
+    /// predict(peptide)
+    /// --
+    /// Predict the retention time of a (modified) peptide sequence. Residues
+    /// not seen during training contribute nothing
+    fn predict(&self, peptide: &str) -> PyResult<f64> {
+        let tokens = residue_tokens(peptide).map_err(PyErr::new::<PyValueError, _>)?;
+        Ok(self.predict_tokens(&tokens))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RtModel(peptides={}, r_squared={:.3})",
+            self.peptides, self.r_squared
+        )
+    }
+}
+
+/// fit_rt_model(psms, /, q_value_cutoff, alpha)
+/// --
+/// Train a retention time model on the confident target PSMs of a single
+/// run, and set the `predicted_rt` and `rt_error` (|observed - predicted|)
+/// features of every PSM. Each peptide contributes the RT of its best
+/// scoring PSM; `alpha` is the ridge penalty. Training peptides are
+/// predicted by cross-fitting: each one by a model fitted on the other
+/// folds, so their `rt_error` is not biased by overfitting. Other PSMs are
+/// predicted by the returned model, trained on all confident peptides
+#[pyfunction(q_value_cutoff = "0.01", alpha = "1.0")]
+pub fn fit_rt_model(
+    mut psms: Vec<PyRefMut<Psm>>,
+    q_value_cutoff: Option<f32>,
+    alpha: Option<f64>,
+) -> PyResult<RtModel> {
+    let q_values = fdr::q_values(
+        &psms
+            .iter()
+            .map(|psm| (psm.hyperscore, psm.decoy))
+            .collect::<Vec<_>>(),
+    );
+    let cutoff = q_value_cutoff.unwrap_or(0.01);
+    let alpha = alpha.unwrap_or(1.0);
+
+    let mut best: HashMap<&str, (f64, f64)> = HashMap::new();
+    for (psm, q) in psms.iter().zip(q_values) {
+        if psm.decoy || q > cutoff {
+            continue;
+        }
+        let entry = best
+            .entry(psm.peptide.as_str())
+            .or_insert((psm.hyperscore, psm.rt as f64));
+        if psm.hyperscore > entry.0 {
+            *entry = (psm.hyperscore, psm.rt as f64);
+        }
+    }
+    let mut peptides = best
+        .into_iter()
+        .filter_map(|(peptide, (_, rt))| Some((residue_tokens(peptide).ok()?, rt)))
+        .collect::<Vec<_>>();
+    // Deterministic training order (and fold assignment), regardless of hashing
+    peptides.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if peptides.len() < MIN_PEPTIDES {
+        return Err(PyErr::new::<PyValueError, _>(format!(
+            "only {} confident peptides, at least {} are required to train an RT model",
+            peptides.len(),
+            MIN_PEPTIDES
+        )));
+    }
+    let singular = || PyErr::new::<PyValueError, _>("unable to fit RT model: singular system");
+    let model = RtModel::fit(&peptides, alpha).ok_or_else(singular)?;
+
+    // Peptide `idx` is held out of fold `idx % FOLDS`
+    let mut held_out: HashMap<&[String], f64> = HashMap::new();
+    for fold in 0..FOLDS {
+        let training = peptides
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| idx % FOLDS != fold)
+            .map(|(_, peptide)| peptide.clone())
+            .collect::<Vec<_>>();
+        let fold_model = RtModel::fit(&training, alpha).ok_or_else(singular)?;
+        for (_, (tokens, _)) in peptides
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| idx % FOLDS == fold)
+        {
+            held_out.insert(tokens, fold_model.predict_tokens(tokens));
+        }
+    }
+
+    for psm in psms.iter_mut() {
+        if let Ok(tokens) = residue_tokens(&psm.peptide) {
+            let predicted = held_out
+                .get(tokens.as_slice())
+                .copied()
+                .unwrap_or_else(|| model.predict_tokens(&tokens));
+            let error = (psm.rt as f64 - predicted).abs();
+            psm.features.insert("predicted_rt".into(), predicted);
+            psm.features.insert("rt_error".into(), error);
+        }
+    }
+    Ok(model)
+}
@@ -0,0 +1,345 @@
+use crate::inference::ProteinGroup;
+use crate::lfq::PrecursorQuant;
+use crate::psm::stripped_sequence;
+use crate::stats::{median, solve};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// MaxLFQ: protein intensities from pairwise median peptide ratios
+    MaxLfq,
+    /// Mean of the N most intense precursors in each sample
+    TopN(usize),
+    /// Tukey's median polish of the log2 precursor × sample matrix
+    MedianPolish,
+}
+
+impl Method {
+    pub fn parse(method: &str, top_n: usize) -> PyResult<Self> {
+        match method.to_lowercase().replace(['-', '_'], "").as_str() {
+            "maxlfq" => Ok(Method::MaxLfq),
+            "topn" | "top" => Ok(Method::TopN(top_n.max(1))),
+            "medianpolish" => Ok(Method::MedianPolish),
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unknown roll-up method: {}. Expected one of maxlfq, topn, median_polish",
+                method
+            ))),
+        }
+    }
+}
+
+/// Protein × sample quantification matrix
+#[pyclass]
+#[derive(Clone)]
+pub struct ProteinMatrix {
+    /// Protein groups (row labels), indistinguishable proteins joined by ';'
+    #[pyo3(get)]
+    pub proteins: Vec<String>,
+    /// Samples (column labels)
+    #[pyo3(get)]
+    pub samples: Vec<String>,
+    /// Protein intensity in each sample, `None` if missing
+    #[pyo3(get)]
+    pub intensities: Vec<Vec<Option<f32>>>,
+    /// Number of quantified precursors contributing to each value
+    #[pyo3(get)]
+    pub precursor_counts: Vec<Vec<usize>>,
+    /// Number of contributing precursors transferred by match-between-runs
+    #[pyo3(get)]
+    pub mbr_counts: Vec<Vec<usize>>,
+    /// Number of samples in which each protein is missing
+    #[pyo3(get)]
+    pub missing: Vec<usize>,
+}
+
+#[pymethods]
+impl ProteinMatrix {
+    fn __repr__(&self) -> String {
+        format!(
+            "ProteinMatrix [{} proteins x {} samples, {} missing values]",
+            self.proteins.len(),
+            self.samples.len(),
+            self.missing.iter().sum::<usize>()
+        )
+    }
+}
+
+/// Precursor × sample matrix of raw intensities for a single protein
+type Matrix = Vec<Vec<Option<f32>>>;
+
+fn top_n(matrix: &Matrix, samples: usize, n: usize) -> Vec<Option<f32>> {
+    (0..samples)
+        .map(|col| {
+            let mut values = matrix.iter().filter_map(|row| row[col]).collect::<Vec<_>>();
+            values.sort_unstable_by(|a, b| b.total_cmp(a));
+            values.truncate(n);
+            match values.len() {
+                0 => None,
+                len => Some(values.iter().sum::<f32>() / len as f32),
+            }
+        })
+        .collect()
+}
+
+fn median_polish(matrix: &Matrix, samples: usize) -> Vec<Option<f32>> {
+    let mut residuals = matrix
+        .iter()
+        .map(|row| row.iter().map(|x| x.map(f32::log2)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut overall = 0.0;
+    let mut row_effect = vec![0.0f32; residuals.len()];
+    let mut col_effect = vec![0.0f32; samples];
+
+    for _ in 0..10 {
+        for (row, effect) in residuals.iter_mut().zip(row_effect.iter_mut()) {
+            let present = row.iter().flatten().copied().collect::<Vec<_>>();
+            if let Some(m) = median(&present) {
+                row.iter_mut().flatten().for_each(|x| *x -= m);
+                *effect += m;
+            }
+        }
+        if let Some(m) = median(&col_effect) {
+            col_effect.iter_mut().for_each(|x| *x -= m);
+            overall += m;
+        }
+        for (col, effect) in col_effect.iter_mut().enumerate() {
+            let present = residuals
+                .iter()
+                .filter_map(|row| row[col])
+                .collect::<Vec<_>>();
+            if let Some(m) = median(&present) {
+                residuals
+                    .iter_mut()
+                    .filter_map(|row| row[col].as_mut())
+                    .for_each(|x| *x -= m);
+                *effect += m;
+            }
+        }
+        if let Some(m) = median(&row_effect) {
+            row_effect.iter_mut().for_each(|x| *x -= m);
+            overall += m;
+        }
+    }
+
+    (0..samples)
+        .map(|col| {
+            matrix
+                .iter()
+                .any(|row| row[col].is_some())
+                .then(|| (overall + col_effect[col]).exp2())
+        })
+        .collect()
+}
+
+fn max_lfq(matrix: &Matrix, samples: usize, min_ratios: usize) -> Vec<Option<f32>> {
+    let logs = matrix
+        .iter()
+        .map(|row| row.iter().map(|x| x.map(f32::log2)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // Pairwise median log-ratios between samples
+    let mut ratios = Vec::new();
+    for j in 0..samples {
+        for k in j + 1..samples {
+            let diffs = logs
+                .iter()
+                .filter_map(|row| Some(row[j]? - row[k]?))
+                .collect::<Vec<_>>();
+            if diffs.len() >= min_ratios.max(1) {
+                if let Some(r) = median(&diffs) {
+                    ratios.push((j, k, r));
+                }
+            }
+        }
+    }
+
+    // Connected components of samples linked by ratios
+    let mut component = (0..samples).collect::<Vec<_>>();
+    fn find(component: &mut [usize], x: usize) -> usize {
+        let mut root = x;
+        while component[root] != root {
+            root = component[root];
+        }
+        component[x] = root;
+        root
+    }
+    for &(j, k, _) in &ratios {
+        let (a, b) = (find(&mut component, j), find(&mut component, k));
+        component[a] = b;
+    }
+
+    let mut result = vec![None; samples];
+    for root in 0..samples {
+        let members = (0..samples)
+            .filter(|&s| find(&mut component, s) == root)
+            .filter(|&s| matrix.iter().any(|row| row[s].is_some()))
+            .collect::<Vec<_>>();
+        if members.is_empty() {
+            continue;
+        }
+        let index = members
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| (s, i))
+            .collect::<HashMap<_, _>>();
+
+        // Least squares: minimize sum (a_j - a_k - r_jk)^2, with sum(a) = 0
+        // enforced by adding a matrix of ones to the graph Laplacian
+        let n = members.len();
+        let mut a = vec![vec![1.0f64; n]; n];
+        let mut b = vec![0.0f64; n];
+        for &(j, k, r) in &ratios {
+            if let (Some(&j), Some(&k)) = (index.get(&j), index.get(&k)) {
+                a[j][j] += 1.0;
+                a[k][k] += 1.0;
+                a[j][k] -= 1.0;
+                a[k][j] -= 1.0;
+                b[j] += r as f64;
+                b[k] -= r as f64;
+            }
+        }
+        let solution = match solve(a, b) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // Rescale so that the total protein intensity matches the summed
+        // precursor intensity across the samples of this component
+        let total = members
+            .iter()
+            .map(|&s| matrix.iter().filter_map(|row| row[s]).sum::<f32>() as f64)
+            .sum::<f64>();
+        let profile = solution.iter().map(|x| x.exp2()).sum::<f64>();
+        let scale = total / profile;
+        for (&s, x) in members.iter().zip(solution) {
+            result[s] = Some((x.exp2() * scale) as f32);
+        }
+    }
+    result
+}
+
+/// Roll up precursor quantification to protein groups.
+///
+/// Precursors are assigned to protein groups through the unique and razor
+/// peptides of each group; decoy groups and groups with a q-value above
+/// `q_value_cutoff` are ignored. Samples correspond to the `file` of each
+/// precursor quantity
+pub fn rollup(
+    quant: &[PyRef<PrecursorQuant>],
+    groups: &[PyRef<ProteinGroup>],
+    method: Method,
+    q_value_cutoff: f32,
+    min_ratios: usize,
+) -> ProteinMatrix {
+    let groups = groups
+        .iter()
+        .filter(|group| !group.decoy && group.q_value <= q_value_cutoff)
+        .collect::<Vec<_>>();
+
+    let mut peptide_to_group = HashMap::new();
+    for (idx, group) in groups.iter().enumerate() {
+        for peptide in group.unique_peptides.iter().chain(&group.razor_peptides) {
+            peptide_to_group.insert(peptide.as_str(), idx);
+        }
+    }
+
+    let mut samples = quant.iter().map(|q| q.file.clone()).collect::<Vec<_>>();
+    samples.sort();
+    samples.dedup();
+    let sample_index = samples
+        .iter()
+        .enumerate()
+        .map(|(idx, s)| (s.as_str(), idx))
+        .collect::<HashMap<_, _>>();
+
+    // Per protein group: precursor -> (intensity, mbr) in each sample
+    let mut precursors: Vec<HashMap<(&str, u8), Vec<Option<(f32, bool)>>>> =
+        vec![HashMap::new(); groups.len()];
+    for q in quant {
+        let group = match peptide_to_group.get(stripped_sequence(&q.peptide).as_str()) {
+            Some(&group) => group,
+            None => continue,
+        };
+        let intensity = match q.intensity {
+            Some(x) if x > 0.0 => x,
+            _ => continue,
+        };
+        let cell = &mut precursors[group]
+            .entry((q.peptide.as_str(), q.charge))
+            .or_insert_with(|| vec![None; samples.len()])[sample_index[q.file.as_str()]];
+        *cell = Some(match cell {
+            Some((x, mbr)) => (*x + intensity, *mbr && q.mbr),
+            None => (intensity, q.mbr),
+        });
+    }
+
+    let mut matrix = ProteinMatrix {
+        proteins: groups
+            .iter()
+            .map(|group| group.proteins.join(";"))
+            .collect(),
+        samples,
+        intensities: Vec::with_capacity(groups.len()),
+        precursor_counts: Vec::with_capacity(groups.len()),
+        mbr_counts: Vec::with_capacity(groups.len()),
+        missing: Vec::with_capacity(groups.len()),
+    };
+    let n = matrix.samples.len();
+    for rows in &precursors {
+        let values = rows
+            .values()
+            .map(|row| row.iter().map(|cell| cell.map(|(x, _)| x)).collect())
+            .collect::<Matrix>();
+        let intensities = match method {
+            Method::MaxLfq => max_lfq(&values, n, min_ratios),
+            Method::TopN(top) => top_n(&values, n, top),
+            Method::MedianPolish => median_polish(&values, n),
+        };
+        let count = |f: fn(&(f32, bool)) -> bool| {
+            (0..n)
+                .map(|col| {
+                    rows.values()
+                        .filter(|row| row[col].as_ref().map_or(false, f))
+                        .count()
+                })
+                .collect::<Vec<_>>()
+        };
+        matrix.precursor_counts.push(count(|_| true));
+        matrix.mbr_counts.push(count(|(_, mbr)| *mbr));
+        matrix
+            .missing
+            .push(intensities.iter().filter(|x| x.is_none()).count());
+        matrix.intensities.push(intensities);
+    }
+    matrix
+}
+
+/// protein_rollup(quant, protein_groups, /, method, top_n, q_value_cutoff, min_ratios)
+/// --
+/// Roll up precursor quantification to a protein × sample matrix, using
+/// MaxLFQ ("maxlfq"), top-N ("topn") or median polish ("median_polish")
+#[pyfunction(
+    method = "\"maxlfq\"",
+    top_n = "3",
+    q_value_cutoff = "0.01",
+    min_ratios = "1"
+)]
+pub fn protein_rollup(
+    quant: Vec<PyRef<PrecursorQuant>>,
+    protein_groups: Vec<PyRef<ProteinGroup>>,
+    method: Option<&str>,
+    top_n: Option<usize>,
+    q_value_cutoff: Option<f32>,
+    min_ratios: Option<usize>,
+) -> PyResult<ProteinMatrix> {
+    let method = Method::parse(method.unwrap_or("maxlfq"), top_n.unwrap_or(3))?;
+    Ok(rollup(
+        &quant,
+        &protein_groups,
+        method,
+        q_value_cutoff.unwrap_or(0.01),
+        min_ratios.unwrap_or(1),
+    ))
+}
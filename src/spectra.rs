@@ -7,8 +7,11 @@ use sage_core::{
 use std::collections::HashMap;
 
 use crate::{
-    lfq::{self, Xic},
+    fragment_tolerance,
+    lfq::{self, ChromatographicPeak, FragmentXic, Xic},
     psm::Psm,
+    tmt::{self, ReporterIons},
+    tolerance, Bounds,
 };
 
 /// A Python module implemented in Rust.
@@ -43,6 +46,9 @@ pub struct Mzml {
     last_scan: usize,
     // Map spectrum title to index into `spectra` vector
     title_to_idx: HashMap<String, usize>,
+    // Raw MSn peaks in the reporter ion region, which are otherwise discarded
+    // by the spectrum processor. Indices match `spectra`
+    reporter_peaks: Vec<Vec<sage_core::spectrum::Peak>>,
 }
 
 impl Mzml {
@@ -62,11 +68,17 @@ impl Mzml {
     #[new]
     pub fn new(path: &str) -> PyResult<Self> {
         let sp = SpectrumProcessor::new(150, 150.0, 2000.0, true, 0);
-        let spectra = sage_cloudpath::read_mzml(path)
+        let (spectra, reporter_peaks): (Vec<_>, Vec<_>) = sage_cloudpath::read_mzml(path)
             .map_err(|e| PyErr::new::<PyFileNotFoundError, _>(path.to_string()))?
             .into_par_iter()
-            .map(|spec| sp.process(spec))
-            .collect::<Vec<_>>();
+            .map(|spec| {
+                let reporter_peaks = match spec.ms_level {
+                    1 => Vec::new(),
+                    _ => tmt::reporter_region(&spec.mz, &spec.intensity),
+                };
+                (sp.process(spec), reporter_peaks)
+            })
+            .unzip();
         let title_to_idx = spectra
             .iter()
             .enumerate()
@@ -78,6 +90,7 @@ impl Mzml {
             spectra,
             last_scan: 0,
             title_to_idx,
+            reporter_peaks,
         })
     }
 
@@ -94,16 +107,136 @@ impl Mzml {
         self.get_sage_spectra(scan).cloned().map(Into::into)
     }
 
+    /// xic(psm, charge, /, rt_tolerance, ppm, da)
+    /// --
     /// Return MS1 peaks for extracted ion chromatogram
     #[args(rt_tolerance = "2.5")]
-    pub fn xic(&self, psm: &Psm, charge: u8, rt_tolerance: Option<f32>) -> Vec<Xic> {
+    pub fn xic(
+        &self,
+        psm: &Psm,
+        charge: u8,
+        rt_tolerance: Option<f32>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+    ) -> PyResult<Vec<Xic>> {
         let tol = rt_tolerance.unwrap_or(2.5);
-        lfq::xic(
+        Ok(lfq::xic(
             &self.spectra,
             psm.rt - tol,
             psm.rt + tol,
-            (psm.expmass - psm.isotope_error - PROTON) / charge as f32,
-        )
+            psm.precursor_mass(charge),
+            tolerance(ppm, da, Tolerance::Ppm(-5.0, 5.0))?,
+        ))
+    }
+
+    /// fragment_xic(precursor_mz, fragment_mz, /, rt_min, rt_max, ppm, da)
+    /// --
+    /// Extract fragment ion chromatograms from the MS2 scans whose isolation
+    /// window covers `precursor_mz`, optionally limited to `rt_min..rt_max`.
+    /// Fragment m/z are treated as singly charged
+    pub fn fragment_xic(
+        &self,
+        precursor_mz: f32,
+        fragment_mz: Vec<f32>,
+        rt_min: Option<f32>,
+        rt_max: Option<f32>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+    ) -> PyResult<FragmentXic> {
+        Ok(lfq::fragment_xic(
+            &self.spectra,
+            rt_min.unwrap_or(f32::MIN),
+            rt_max.unwrap_or(f32::MAX),
+            precursor_mz,
+            fragment_mz,
+            fragment_tolerance(ppm, da)?,
+        ))
+    }
+
+    /// isotope_xic(psm, charge, /, isotopes, rt_tolerance, ppm, da)
+    /// --
+    /// Return MS1 peaks for the M, M+1, ... M+`isotopes - 1` extracted ion
+    /// chromatograms, grouped by isotope
+    #[args(isotopes = "3", rt_tolerance = "2.5")]
+    pub fn isotope_xic(
+        &self,
+        psm: &Psm,
+        charge: u8,
+        isotopes: Option<usize>,
+        rt_tolerance: Option<f32>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+    ) -> PyResult<Vec<Vec<Xic>>> {
+        let tol = rt_tolerance.unwrap_or(2.5);
+        Ok(lfq::isotope_xic(
+            &self.spectra,
+            psm.rt - tol,
+            psm.rt + tol,
+            psm.precursor_mass(charge),
+            charge,
+            isotopes.unwrap_or(3),
+            tolerance(ppm, da, Tolerance::Ppm(-5.0, 5.0))?,
+        ))
+    }
+
+    /// integrate(psm, charge, /, rt_tolerance, ppm, da, smoothing)
+    /// --
+    /// Detect and integrate the MS1 chromatographic peak of the PSM precursor
+    #[args(rt_tolerance = "2.5", smoothing = "3")]
+    pub fn integrate(
+        &self,
+        psm: &Psm,
+        charge: u8,
+        rt_tolerance: Option<f32>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+        smoothing: Option<usize>,
+    ) -> PyResult<Option<ChromatographicPeak>> {
+        let tol = rt_tolerance.unwrap_or(2.5);
+        let trace = lfq::trace(
+            &self.spectra,
+            psm.rt - tol,
+            psm.rt + tol,
+            psm.precursor_mass(charge),
+            tolerance(ppm, da, Tolerance::Ppm(-5.0, 5.0))?,
+        );
+        Ok(lfq::integrate(&trace, Some(psm.rt), smoothing.unwrap_or(3)))
+    }
+
+    /// reporter_ions(label, /, level, ppm, da, impurities)
+    /// --
+    /// Extract isobaric reporter ion intensities from every MSn scan.
+    /// `label` is one of tmt6, tmt10, tmt11, tmt16, tmt18, itraq4, itraq8.
+    /// If `level` is not supplied, MS3 scans are used when present, otherwise MS2
+    pub fn reporter_ions(
+        &self,
+        label: &str,
+        level: Option<u8>,
+        ppm: Option<Bounds>,
+        da: Option<Bounds>,
+        impurities: Option<Vec<Vec<f32>>>,
+    ) -> PyResult<Vec<ReporterIons>> {
+        let label = tmt::Isobaric::parse(label)?;
+        let tolerance = tolerance(ppm, da, Tolerance::Ppm(-20.0, 20.0))?;
+        let level = level.unwrap_or_else(|| {
+            match self.spectra.iter().any(|spectrum| spectrum.level == 3) {
+                true => 3,
+                false => 2,
+            }
+        });
+
+        self.spectra
+            .par_iter()
+            .zip(self.reporter_peaks.par_iter())
+            .filter(|(spectrum, _)| spectrum.level == level)
+            .map(|(spectrum, peaks)| {
+                let mut reporters = tmt::quantify(spectrum, peaks, label, tolerance);
+                if let Some(impurities) = &impurities {
+                    reporters.intensities = tmt::correct(&reporters.intensities, impurities)?;
+                }
+                Ok(reporters)
+            })
+            .collect()
     }
 
     fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
@@ -137,6 +270,12 @@ pub struct Precursor {
 
 #[pymethods]
 impl Precursor {
+    #[getter]
+    /// Isolation window as absolute (lower, upper) m/z bounds, if known
+    fn isolation_window(&self) -> Option<(f32, f32)> {
+        self.isolation_window.map(|w| w.bounds(self.mz))
+    }
+
     fn __repr__(&self) -> PyResult<String> {
         Ok(format!(
             "Precursor scan {:?}: m/z={}, z={:?}, int={:?}",
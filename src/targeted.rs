@@ -0,0 +1,424 @@
+use crate::lfq::{self, ChromatographicPeak, FragmentXic};
+use crate::psm::stripped_sequence;
+use crate::spectra::Mzml;
+use crate::{fragment_tolerance, Bounds};
+use pyo3::exceptions::{PyFileNotFoundError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use sage_core::mass::{Tolerance, PROTON};
+use std::collections::HashMap;
+
+/// A single precursor/fragment transition of a PRM or SRM assay
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct Transition {
+    #[pyo3(get, set)]
+    pub peptide: String,
+    #[pyo3(get, set)]
+    pub protein: Option<String>,
+    #[pyo3(get, set)]
+    pub precursor_mz: f32,
+    #[pyo3(get, set)]
+    pub charge: u8,
+    #[pyo3(get, set)]
+    pub fragment_mz: f32,
+    #[pyo3(get, set)]
+    pub fragment_charge: u8,
+    /// Fragment ion label, e.g. y7
+    #[pyo3(get, set)]
+    pub fragment: Option<String>,
+    #[pyo3(get, set)]
+    pub rt_start: Option<f32>,
+    #[pyo3(get, set)]
+    pub rt_end: Option<f32>,
+    /// Relative library intensity, used for the library dot product
+    #[pyo3(get, set)]
+    pub library_intensity: Option<f32>,
+    /// Transition of an isotope-labelled (heavy) internal standard
+    #[pyo3(get, set)]
+    pub heavy: bool,
+}
+
+#[pymethods]
+impl Transition {
+    fn __repr__(&self) -> String {
+        format!(
+            "{}/{} {:.4} -> {:.4}{}",
+            self.peptide,
+            self.charge,
+            self.precursor_mz,
+            self.fragment_mz,
+            if self.heavy { " (heavy)" } else { "" }
+        )
+    }
+}
+
+/// Split a delimited line into fields, honoring double quotes
+pub(crate) fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Column names accepted for each transition field. Covers Skyline
+/// transition list and report exports, as well as plain CSV files
+const COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "peptide",
+        &[
+            "peptidemodifiedsequence",
+            "modifiedsequence",
+            "peptide",
+            "peptidesequence",
+            "sequence",
+        ],
+    ),
+    ("protein", &["proteinname", "protein", "proteinaccession"]),
+    ("precursor_mz", &["precursormz", "q1"]),
+    ("charge", &["precursorcharge", "charge"]),
+    ("fragment_mz", &["productmz", "fragmentmz", "q3"]),
+    ("fragment_charge", &["productcharge", "fragmentcharge"]),
+    (
+        "fragment",
+        &["fragmention", "fragment", "ion", "annotation"],
+    ),
+    (
+        "rt_start",
+        &["starttime", "rtstart", "rtmin", "minstarttime"],
+    ),
+    ("rt_end", &["endtime", "rtend", "rtmax", "maxendtime"]),
+    ("rt", &["explicitretentiontime", "retentiontime", "rt"]),
+    (
+        "rt_window",
+        &[
+            "explicitretentiontimewindow",
+            "retentiontimewindow",
+            "rtwindow",
+        ],
+    ),
+    (
+        "library_intensity",
+        &["libraryintensity", "relativeintensity"],
+    ),
+    (
+        "label",
+        &["isotopelabeltype", "isotopelabel", "label", "labeltype"],
+    ),
+];
+
+/// Detect the delimiter of a CSV/TSV header line, and find the index of each
+/// field in `aliases`. Column names are compared after lowercasing and
+/// removing anything but letters and digits
+pub(crate) fn header_columns(
+    header: &str,
+    aliases: &[(&'static str, &[&str])],
+) -> (char, HashMap<&'static str, usize>) {
+    let delimiter = match header.contains('\t') {
+        true => '\t',
+        false => ',',
+    };
+    let names = split_line(header, delimiter)
+        .into_iter()
+        .map(|name| {
+            name.chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_ascii_lowercase()
+        })
+        .collect::<Vec<_>>();
+    let columns = aliases
+        .iter()
+        .filter_map(|(field, aliases)| {
+            aliases
+                .iter()
+                .find_map(|alias| names.iter().position(|name| name == alias))
+                .map(|idx| (*field, idx))
+        })
+        .collect();
+    (delimiter, columns)
+}
+
+/// Parse a transition list in CSV or TSV format
+pub fn parse_transitions(contents: &str) -> Result<Vec<Transition>, String> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().ok_or("empty transition list")?;
+    let (delimiter, columns) = header_columns(header, COLUMNS);
+    for required in ["peptide", "precursor_mz", "fragment_mz"] {
+        if !columns.contains_key(required) {
+            return Err(format!("transition list has no `{}` column", required));
+        }
+    }
+
+    let mut transitions = Vec::new();
+    for (line_number, line) in lines.enumerate() {
+        let fields = split_line(line, delimiter);
+        let get = |field: &str| {
+            columns
+                .get(field)
+                .and_then(|&idx| fields.get(idx))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        let number = |field: &str| -> Result<Option<f32>, String> {
+            get(field)
+                .map(|value| {
+                    value.parse::<f32>().map_err(|_| {
+                        format!("invalid {} `{}` on line {}", field, value, line_number + 2)
+                    })
+                })
+                .transpose()
+        };
+
+        let (mut rt_start, mut rt_end) = (number("rt_start")?, number("rt_end")?);
+        if let (Some(rt), Some(window)) = (number("rt")?, number("rt_window")?) {
+            rt_start = rt_start.or(Some(rt - window / 2.0));
+            rt_end = rt_end.or(Some(rt + window / 2.0));
+        }
+        transitions.push(Transition {
+            peptide: get("peptide").unwrap_or_default().to_string(),
+            protein: get("protein").map(String::from),
+            precursor_mz: number("precursor_mz")?.unwrap_or_default(),
+            charge: number("charge")?.map_or(2, |z| z as u8),
+            fragment_mz: number("fragment_mz")?.unwrap_or_default(),
+            fragment_charge: number("fragment_charge")?.map_or(1, |z| z as u8),
+            fragment: get("fragment").map(String::from),
+            rt_start,
+            rt_end,
+            library_intensity: number("library_intensity")?,
+            heavy: get("label")
+                .filter(|label| label.eq_ignore_ascii_case("heavy"))
+                .is_some(),
+        });
+    }
+    Ok(transitions)
+}
+
+/// read_transitions(path)
+/// --
+/// Read a PRM/SRM transition list from a CSV/TSV file, such as a Skyline
+/// transition list export
+#[pyfunction]
+pub fn read_transitions(path: &str) -> PyResult<Vec<Transition>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| PyErr::new::<PyFileNotFoundError, _>(path.to_string()))?;
+    parse_transitions(&contents).map_err(PyErr::new::<PyValueError, _>)
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct TargetedQuant {
+    #[pyo3(get)]
+    pub peptide: String,
+    #[pyo3(get)]
+    pub protein: Option<String>,
+    #[pyo3(get)]
+    pub charge: u8,
+    #[pyo3(get)]
+    pub precursor_mz: f32,
+    #[pyo3(get)]
+    pub heavy: bool,
+    /// Fragment ion labels (or m/z, if unlabelled), in transition order
+    #[pyo3(get)]
+    pub fragments: Vec<String>,
+    /// Extracted fragment chromatograms, in transition order
+    #[pyo3(get)]
+    pub xic: FragmentXic,
+    /// Peak detected in the summed fragment chromatogram
+    #[pyo3(get)]
+    pub peak: Option<ChromatographicPeak>,
+    /// Area of each fragment within the peak boundaries
+    #[pyo3(get)]
+    pub areas: Vec<f32>,
+    #[pyo3(get)]
+    pub total_area: f32,
+    /// Normalized dot product of square-root fragment areas and library
+    /// intensities, if library intensities are available
+    #[pyo3(get)]
+    pub library_dot_product: Option<f32>,
+    /// Ratio of the total area to that of the matching heavy standard
+    #[pyo3(get)]
+    pub ratio_to_heavy: Option<f32>,
+}
+
+#[pymethods]
+impl TargetedQuant {
+    fn __repr__(&self) -> String {
+        format!(
+            "{}/{}{} area={}",
+            self.peptide,
+            self.charge,
+            if self.heavy { " (heavy)" } else { "" },
+            self.total_area
+        )
+    }
+}
+
+/// Trapezoidal area of a trace between two retention times
+fn area(rt: &[f32], intensities: &[f32], start: f32, end: f32) -> f32 {
+    (1..rt.len())
+        .filter(|&idx| rt[idx - 1] >= start && rt[idx] <= end)
+        .map(|idx| (rt[idx] - rt[idx - 1]) * (intensities[idx - 1] + intensities[idx]) / 2.0)
+        .sum()
+}
+
+fn dot_product(observed: &[f32], library: &[f32]) -> Option<f32> {
+    let (a, b): (Vec<f32>, Vec<f32>) = observed
+        .iter()
+        .zip(library)
+        .map(|(x, y)| (x.max(0.0).sqrt(), y.max(0.0).sqrt()))
+        .unzip();
+    let norm = (a.iter().map(|x| x * x).sum::<f32>() * b.iter().map(|x| x * x).sum::<f32>()).sqrt();
+    match norm > 0.0 {
+        true => Some(a.iter().zip(&b).map(|(x, y)| x * y).sum::<f32>() / norm),
+        false => None,
+    }
+}
+
+fn quantify_precursor(
+    spectra: &[sage_core::spectrum::ProcessedSpectrum],
+    transitions: &[&Transition],
+    tolerance: Tolerance,
+    smoothing: usize,
+) -> TargetedQuant {
+    let first = transitions[0];
+    let rt_start = transitions
+        .iter()
+        .filter_map(|t| t.rt_start)
+        .reduce(f32::min);
+    let rt_end = transitions.iter().filter_map(|t| t.rt_end).reduce(f32::max);
+
+    // Fragment peaks are deconvoluted, so convert each fragment to its
+    // singly charged m/z
+    let fragment_mz = transitions
+        .iter()
+        .map(|t| (t.fragment_mz - PROTON) * t.fragment_charge.max(1) as f32 + PROTON)
+        .collect::<Vec<_>>();
+    let mut xic = lfq::fragment_xic(
+        spectra,
+        rt_start.unwrap_or(f32::MIN),
+        rt_end.unwrap_or(f32::MAX),
+        first.precursor_mz,
+        fragment_mz,
+        tolerance,
+    );
+    xic.fragment_mz = transitions.iter().map(|t| t.fragment_mz).collect();
+
+    let summed = xic
+        .rt
+        .iter()
+        .enumerate()
+        .map(|(idx, &rt)| {
+            (
+                rt,
+                xic.intensities.iter().map(|trace| trace[idx]).sum::<f32>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let target_rt = rt_start.zip(rt_end).map(|(start, end)| (start + end) / 2.0);
+    let peak = lfq::integrate(&summed, target_rt, smoothing);
+
+    let areas = match &peak {
+        Some(peak) => xic
+            .intensities
+            .iter()
+            .map(|trace| area(&xic.rt, trace, peak.start_rt, peak.end_rt))
+            .collect::<Vec<_>>(),
+        None => vec![0.0; transitions.len()],
+    };
+    let library = transitions
+        .iter()
+        .map(|t| t.library_intensity)
+        .collect::<Option<Vec<_>>>();
+
+    TargetedQuant {
+        peptide: first.peptide.clone(),
+        protein: first.protein.clone(),
+        charge: first.charge,
+        precursor_mz: first.precursor_mz,
+        heavy: first.heavy,
+        fragments: transitions
+            .iter()
+            .map(|t| {
+                t.fragment
+                    .clone()
+                    .unwrap_or_else(|| format!("{:.4}", t.fragment_mz))
+            })
+            .collect(),
+        total_area: areas.iter().sum(),
+        library_dot_product: library
+            .filter(|library| library.len() > 1)
+            .and_then(|library| dot_product(&areas, &library)),
+        ratio_to_heavy: None,
+        areas,
+        peak,
+        xic,
+    }
+}
+
+/// quantify(mzml, transitions, /, ppm, da, smoothing)
+/// --
+/// Targeted (PRM) quantification. Transitions are grouped by peptide,
+/// precursor charge and label. For each precursor, fragment chromatograms
+/// are extracted from the MS2 scans whose isolation window covers it, within
+/// the transition RT window, and integrated over the peak detected in their sum
+#[pyfunction(smoothing = "3")]
+pub fn quantify(
+    mzml: &Mzml,
+    transitions: Vec<Transition>,
+    ppm: Option<Bounds>,
+    da: Option<Bounds>,
+    smoothing: Option<usize>,
+) -> PyResult<Vec<TargetedQuant>> {
+    let tolerance = fragment_tolerance(ppm, da)?;
+    let smoothing = smoothing.unwrap_or(3);
+
+    // Group transitions by precursor, keeping the order of the transition list
+    let mut groups: HashMap<(&str, u8, bool), usize> = HashMap::new();
+    let mut precursors: Vec<Vec<&Transition>> = Vec::new();
+    for transition in &transitions {
+        let key = (
+            transition.peptide.as_str(),
+            transition.charge,
+            transition.heavy,
+        );
+        let idx = *groups.entry(key).or_insert_with(|| {
+            precursors.push(Vec::new());
+            precursors.len() - 1
+        });
+        precursors[idx].push(transition);
+    }
+
+    let mut quant = precursors
+        .par_iter()
+        .map(|transitions| quantify_precursor(&mzml.spectra, transitions, tolerance, smoothing))
+        .collect::<Vec<_>>();
+
+    // Heavy standards may carry the label in their sequence, so match light
+    // and heavy precursors on the unmodified sequence
+    let heavy = quant
+        .iter()
+        .filter(|q| q.heavy)
+        .map(|q| ((stripped_sequence(&q.peptide), q.charge), q.total_area))
+        .collect::<HashMap<_, _>>();
+    for q in quant.iter_mut().filter(|q| !q.heavy) {
+        q.ratio_to_heavy = heavy
+            .get(&(stripped_sequence(&q.peptide), q.charge))
+            .filter(|&&area| area > 0.0)
+            .map(|area| q.total_area / area);
+    }
+    Ok(quant)
+}
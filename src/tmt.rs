@@ -0,0 +1,238 @@
+use crate::psm::Psm;
+use crate::stats::solve;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use sage_core::mass::{Tolerance, PROTON};
+use sage_core::spectrum::{Peak, ProcessedSpectrum};
+use std::collections::HashMap;
+
+/// Lower bound of the m/z region retained for reporter ion quantification
+pub const REPORTER_MIN_MZ: f32 = 110.0;
+/// Upper bound of the m/z region retained for reporter ion quantification
+pub const REPORTER_MAX_MZ: f32 = 136.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Isobaric {
+    Tmt6,
+    Tmt10,
+    Tmt11,
+    Tmt16,
+    Tmt18,
+    Itraq4,
+    Itraq8,
+}
+
+/// TMT/TMTpro reporter ion m/z, in order of channel
+const TMT: [(&str, f32); 18] = [
+    ("126", 126.127726),
+    ("127N", 127.124761),
+    ("127C", 127.131081),
+    ("128N", 128.128116),
+    ("128C", 128.134436),
+    ("129N", 129.131471),
+    ("129C", 129.137790),
+    ("130N", 130.134825),
+    ("130C", 130.141145),
+    ("131N", 131.138180),
+    ("131C", 131.144500),
+    ("132N", 132.141535),
+    ("132C", 132.147855),
+    ("133N", 133.144890),
+    ("133C", 133.151210),
+    ("134N", 134.148245),
+    ("134C", 134.154565),
+    ("135N", 135.151600),
+];
+
+const TMT6: [(&str, f32); 6] = [
+    ("126", 126.127726),
+    ("127", 127.124761),
+    ("128", 128.134436),
+    ("129", 129.131471),
+    ("130", 130.141145),
+    ("131", 131.138180),
+];
+
+const ITRAQ4: [(&str, f32); 4] = [
+    ("114", 114.111228),
+    ("115", 115.108263),
+    ("116", 116.111618),
+    ("117", 117.114973),
+];
+
+const ITRAQ8: [(&str, f32); 8] = [
+    ("113", 113.107873),
+    ("114", 114.111228),
+    ("115", 115.108263),
+    ("116", 116.111618),
+    ("117", 117.114973),
+    ("118", 118.112008),
+    ("119", 119.115363),
+    ("121", 121.122072),
+];
+
+impl Isobaric {
+    pub fn parse(label: &str) -> PyResult<Self> {
+        match label.to_lowercase().replace(['-', '_'], "").as_str() {
+            "tmt6" | "tmt6plex" => Ok(Isobaric::Tmt6),
+            "tmt10" | "tmt10plex" => Ok(Isobaric::Tmt10),
+            "tmt11" | "tmt11plex" => Ok(Isobaric::Tmt11),
+            "tmt16" | "tmt16plex" | "tmtpro" | "tmtpro16" => Ok(Isobaric::Tmt16),
+            "tmt18" | "tmt18plex" | "tmtpro18" => Ok(Isobaric::Tmt18),
+            "itraq4" | "itraq4plex" => Ok(Isobaric::Itraq4),
+            "itraq8" | "itraq8plex" => Ok(Isobaric::Itraq8),
+            _ => Err(PyErr::new::<PyValueError, _>(format!(
+                "unknown isobaric label: {}",
+                label
+            ))),
+        }
+    }
+
+    /// Channel names and reporter ion m/z
+    pub fn channels(&self) -> Vec<(&'static str, f32)> {
+        match self {
+            Isobaric::Tmt6 => TMT6.to_vec(),
+            Isobaric::Tmt10 => TMT[..10].to_vec(),
+            Isobaric::Tmt11 => TMT[..11].to_vec(),
+            // TMTpro 16-plex skips the 134C/135N channels of the 18-plex kit
+            Isobaric::Tmt16 => TMT[..16].to_vec(),
+            Isobaric::Tmt18 => TMT.to_vec(),
+            Isobaric::Itraq4 => ITRAQ4.to_vec(),
+            Isobaric::Itraq8 => ITRAQ8.to_vec(),
+        }
+    }
+}
+
+/// Reporter ion intensities for a single MS2 or MS3 spectrum
+#[pyclass]
+#[derive(Clone)]
+pub struct ReporterIons {
+    /// Title of the spectrum containing the reporter ions
+    #[pyo3(get)]
+    pub spectrum_title: String,
+    /// Title of the MS2 spectrum used for identification. For SPS-MS3 scans,
+    /// this is the MS2 scan referenced by the MS3 precursor
+    #[pyo3(get)]
+    pub ms2_title: String,
+    /// MS level of the spectrum containing the reporter ions
+    #[pyo3(get)]
+    pub level: u8,
+    /// Channel names
+    #[pyo3(get)]
+    pub channels: Vec<String>,
+    /// Reporter ion intensity for each channel, 0 if not observed
+    #[pyo3(get)]
+    pub intensities: Vec<f32>,
+}
+
+#[pymethods]
+impl ReporterIons {
+    fn __repr__(&self) -> String {
+        format!("{} {:?}", self.ms2_title, self.intensities)
+    }
+}
+
+/// Keep the peaks of a raw spectrum that fall within the reporter ion region.
+/// These are stored as singly-charged masses, like other sage peaks
+pub fn reporter_region(mz: &[f32], intensity: &[f32]) -> Vec<Peak> {
+    mz.iter()
+        .zip(intensity)
+        .filter(|(mz, _)| (REPORTER_MIN_MZ..=REPORTER_MAX_MZ).contains(*mz))
+        .map(|(&mz, &intensity)| Peak {
+            mass: mz - PROTON,
+            intensity,
+        })
+        .collect()
+}
+
+/// Correct observed reporter intensities for isotopic impurities.
+///
+/// `impurities[i][j]` is the fraction of the signal of reagent `i` that is
+/// observed in channel `j` (rows are normalized to sum to 1, so percentages
+/// from a product data sheet can be passed directly, but each row must have a
+/// positive sum). Negative corrected intensities are clamped to 0
+pub fn correct(intensities: &[f32], impurities: &[Vec<f32>]) -> PyResult<Vec<f32>> {
+    let n = intensities.len();
+    if impurities.len() != n || impurities.iter().any(|row| row.len() != n) {
+        return Err(PyErr::new::<PyValueError, _>(format!(
+            "impurity matrix must be {}x{}",
+            n, n
+        )));
+    }
+
+    // observed[j] = sum_i true[i] * impurities[i][j], so solve transpose(P) * x = observed
+    let mut a = vec![vec![0.0; n]; n];
+    for (i, row) in impurities.iter().enumerate() {
+        let total = row.iter().sum::<f32>();
+        // Also catches NaN entries
+        if !(total > 0.0 && total.is_finite()) {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "impurity row {} must have a positive, finite sum (got {})",
+                i, total
+            )));
+        }
+        for (j, fraction) in row.iter().enumerate() {
+            a[j][i] = (fraction / total) as f64;
+        }
+    }
+    let b = intensities.iter().map(|&x| x as f64).collect();
+    let x =
+        solve(a, b).ok_or_else(|| PyErr::new::<PyValueError, _>("impurity matrix is singular"))?;
+    Ok(x.into_iter().map(|x| x.max(0.0) as f32).collect())
+}
+
+/// Extract reporter ion intensities from a spectrum, taking the most intense
+/// peak within `tolerance` of each reporter ion m/z
+pub fn quantify(
+    spectrum: &ProcessedSpectrum,
+    peaks: &[Peak],
+    label: Isobaric,
+    tolerance: Tolerance,
+) -> ReporterIons {
+    let channels = label.channels();
+    let intensities = channels
+        .iter()
+        .map(|(_, mz)| {
+            let (lo, hi) = tolerance.bounds(mz - PROTON);
+            peaks
+                .iter()
+                .filter(|peak| peak.mass >= lo && peak.mass <= hi)
+                .map(|peak| peak.intensity)
+                .fold(0.0f32, f32::max)
+        })
+        .collect();
+
+    let ms2_title = match spectrum.level {
+        2 => spectrum.id.clone(),
+        _ => spectrum
+            .precursors
+            .first()
+            .and_then(|precursor| precursor.spectrum_ref.clone())
+            .unwrap_or_else(|| spectrum.id.clone()),
+    };
+
+    ReporterIons {
+        spectrum_title: spectrum.id.clone(),
+        ms2_title,
+        level: spectrum.level,
+        channels: channels.iter().map(|(name, _)| name.to_string()).collect(),
+        intensities,
+    }
+}
+
+/// attach_reporter_ions(psms, reporter_ions)
+/// --
+/// Set `Psm.reporter_ions` for each PSM, matching PSM spectrum titles to the
+/// MS2 scan that reporter ions were quantified for
+#[pyfunction]
+pub fn attach_reporter_ions(psms: Vec<PyRefMut<Psm>>, reporter_ions: Vec<PyRef<ReporterIons>>) {
+    let by_title = reporter_ions
+        .iter()
+        .map(|r| (r.ms2_title.as_str(), &r.intensities))
+        .collect::<HashMap<_, _>>();
+    for mut psm in psms {
+        if let Some(intensities) = by_title.get(psm.spectrum_title.as_str()) {
+            psm.reporter_ions = Some(intensities.to_vec());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+/// A modification from the UNIMOD database
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Modification {
+    /// UNIMOD accession number
+    pub accession: u32,
+    /// UNIMOD PSI-MS name
+    pub name: &'static str,
+    /// Monoisotopic mass delta
+    pub monoisotopic: f32,
+    /// Common sites of modification: residues, or `^`/`$` for the peptide
+    /// N/C-terminus and `[`/`]` for the protein N/C-terminus
+    pub sites: &'static str,
+}
+
+impl Modification {
+    pub fn label(&self) -> String {
+        format!("{} (UNIMOD:{})", self.name, self.accession)
+    }
+}
+
+macro_rules! unimod {
+    ($($accession:literal, $name:literal, $mass:literal, $sites:literal;)*) => {
+        /// A curated subset of UNIMOD: about 45 commonly searched
+        /// modifications, not the full database
+        pub const UNIMOD: &[Modification] = &[
+            $(Modification { accession: $accession, name: $name, monoisotopic: $mass, sites: $sites },)*
+        ];
+    };
+}
+
+unimod! {
+    1, "Acetyl", 42.010565, "^K[";
+    2, "Amidated", -0.984016, "$]";
+    3, "Biotin", 226.077598, "K^";
+    4, "Carbamidomethyl", 57.021464, "C";
+    5, "Carbamyl", 43.005814, "K^";
+    6, "Carboxymethyl", 58.005479, "C";
+    7, "Deamidated", 0.984016, "NQR";
+    21, "Phospho", 79.966331, "STY";
+    23, "Dehydrated", -18.010565, "ST";
+    24, "Propionamide", 71.037114, "C";
+    26, "Pyro-carbamidomethyl", 39.994915, "C";
+    27, "Glu->pyro-Glu", -18.010565, "E";
+    28, "Gln->pyro-Glu", -17.026549, "Q";
+    30, "Cation:Na", 21.981943, "DE$";
+    34, "Methyl", 14.01565, "KR";
+    35, "Oxidation", 15.994915, "MW";
+    36, "Dimethyl", 28.0313, "KR^";
+    37, "Trimethyl", 42.04695, "K";
+    39, "Methylthio", 45.987721, "C";
+    40, "Sulfo", 79.956815, "Y";
+    41, "Hex", 162.052824, "KN";
+    43, "HexNAc", 203.079373, "NST";
+    58, "Propionyl", 56.026215, "K^";
+    64, "Succinyl", 100.016044, "K";
+    121, "GG", 114.042927, "K";
+    122, "Formyl", 27.994915, "K^";
+    188, "Label:13C(6)", 6.020129, "KR";
+    214, "iTRAQ4plex", 144.102063, "K^";
+    259, "Label:13C(6)15N(2)", 8.014199, "K";
+    267, "Label:13C(6)15N(4)", 10.008269, "R";
+    280, "Ethyl", 28.0313, "DE";
+    312, "Cysteinyl", 119.004099, "C";
+    345, "Trioxidation", 47.984744, "C";
+    354, "Nitro", 44.985078, "Y";
+    385, "Ammonia-loss", -17.026549, "C";
+    425, "Dioxidation", 31.989829, "MW";
+    526, "Dethiomethyl", -48.003371, "M";
+    530, "Cation:K", 37.955882, "DE$";
+    730, "iTRAQ8plex", 304.20536, "K^";
+    737, "TMT6plex", 229.162932, "K^";
+    747, "Malonyl", 86.000394, "K";
+    765, "Met-loss", -131.040485, "[";
+    766, "Met-loss+Acetyl", -89.02992, "[";
+    1363, "Crotonyl", 68.026215, "K";
+    2016, "TMTpro", 304.207146, "K^";
+}
+
+/// Modifications with a mass delta within `tolerance` Da of `delta`
+pub fn annotate(delta: f32, tolerance: f32) -> Vec<&'static Modification> {
+    UNIMOD
+        .iter()
+        .filter(|m| (m.monoisotopic - delta).abs() <= tolerance)
+        .collect()
+}
+
+/// Look up a modification by PSI-MS name (case-insensitive) or accession,
+/// e.g. `Oxidation`, `UNIMOD:35` or `35`
+pub fn lookup(key: &str) -> Option<&'static Modification> {
+    let accession = key
+        .strip_prefix("UNIMOD:")
+        .or_else(|| key.strip_prefix("unimod:"))
+        .unwrap_or(key);
+    match accession.parse::<u32>() {
+        Ok(accession) => UNIMOD.iter().find(|m| m.accession == accession),
+        Err(_) => UNIMOD.iter().find(|m| m.name.eq_ignore_ascii_case(key)),
+    }
+}
+
+/// Parse a modification as written in a modification specification or a
+/// ProForma bracket: a UNIMOD accession (`UNIMOD:35`), a signed mass shift
+/// (`+15.9949`) or a UNIMOD name (`Oxidation`, optionally as `U:Oxidation`).
+/// Unsigned numbers are rejected, as they could be either of the first two
+pub fn parse(name: &str) -> Result<(f32, Option<&'static Modification>), String> {
+    let name = name.trim();
+    let unknown = || {
+        format!(
+            "unknown modification `{}`: expected a UNIMOD name, accession (`UNIMOD:35`) \
+             or signed mass (`+15.9949`)",
+            name
+        )
+    };
+    if name.starts_with(['+', '-']) {
+        return name
+            .parse::<f32>()
+            .map(|mass| (mass, None))
+            .map_err(|_| unknown());
+    }
+    if name.parse::<f32>().is_ok() {
+        return Err(format!(
+            "ambiguous modification `{}`: write `UNIMOD:{}` for an accession, \
+             or `+{}` for a mass shift",
+            name, name, name
+        ));
+    }
+    let accession = name
+        .get(..7)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("unimod:"))
+        .map(|_| &name[7..]);
+    let m = match accession {
+        Some(accession) => accession
+            .parse::<u32>()
+            .ok()
+            .and_then(|accession| UNIMOD.iter().find(|m| m.accession == accession)),
+        None => lookup(name.strip_prefix("U:").unwrap_or(name)),
+    };
+    m.map(|m| (m.monoisotopic, Some(m))).ok_or_else(unknown)
+}